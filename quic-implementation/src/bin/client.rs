@@ -1,19 +1,19 @@
 use futures::executor::block_on;
+use quic_implementation::client::DownloadsFailed;
+use quic_implementation::commons::validation::ConfigError;
+use tracing::error;
 
 #[tokio::main]
 async fn main() {
-    println!("begin");
     let future = quic_implementation::client::run_client();
-    println!("tattaratta");
-    println!("diocaro");
-    match block_on(future) {
-        Ok(_) => {
-            println!("tutto ok");
+    if let Err(e) = block_on(future) {
+        error!("{}", e);
+        if let Some(config_error) = e.downcast_ref::<ConfigError>() {
+            std::process::exit(config_error.exit_code());
         }
-        Err(e) => {
-            eprintln!("{}", e);
+        if let Some(failed) = e.downcast_ref::<DownloadsFailed>() {
+            std::process::exit(failed.exit_code());
         }
-    };
-    println!("abbiamo vinto");
-    println!("finisheeed");
+        std::process::exit(1);
+    }
 }
@@ -1,13 +1,15 @@
 use futures::executor::block_on;
+use quic_implementation::commons::validation::ConfigError;
 
 #[tokio::main]
 async fn main() {
     let future = quic_implementation::server::run_server();
-    match block_on(future) {
-        Ok(_) => {
+    if let Err(e) = block_on(future) {
+        // The logs are only set up once the configuration is read
+        eprintln!("{}", e);
+        if let Some(config_error) = e.downcast_ref::<ConfigError>() {
+            std::process::exit(config_error.exit_code());
         }
-        Err(e) => {
-            eprintln!("{}", e);
-        }
-    };
+        std::process::exit(1);
+    }
 }
@@ -0,0 +1,321 @@
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use futures::future;
+use serde::Serialize;
+use tracing::info;
+
+use super::super::server;
+use super::env_parser::{BenchConfig, Config};
+use super::{connect, make_client_config, make_endpoint, resolve, server_name};
+
+/// The name of the JSON report written into the logs directory.
+const REPORT_FILE: &str = "bench_report.json";
+
+/// One download.
+struct Sample {
+    size: u64,
+    bytes: u64,
+    duration: Duration,
+}
+
+#[derive(Serialize, Debug)]
+struct BenchReport {
+    url: String,
+    connections: usize,
+    streams: usize,
+    duration_s: f64,
+    requests: usize,
+    bytes: u64,
+    goodput_mbit_s: f64,
+    cpu_time_s: Option<f64>,
+    peak_rss_kib: Option<u64>,
+    sizes: Vec<SizeReport>,
+}
+
+/// The goodput percentiles of the downloads of one payload size.
+#[derive(Serialize, Debug)]
+struct SizeReport {
+    size: u64,
+    requests: usize,
+    p50_mbit_s: f64,
+    p90_mbit_s: f64,
+    p99_mbit_s: f64,
+}
+
+/// It downloads payloads of every size, round robin, on all the streams of all the connections
+/// until the duration is over, then reports the goodput, the CPU time and the memory used.
+pub async fn run(mut config: Config, bench: BenchConfig) -> Result<(), Box<dyn Error>> {
+    let sizes = bench.payload_sizes()?;
+    let (base, _local_dir) = match &bench.url {
+        Some(url) => (url.trim_end_matches('/').to_string(), None),
+        None => {
+            let (url, dir) = start_local_server(&mut config).await?;
+            (url, Some(dir))
+        }
+    };
+    let uris = sizes
+        .iter()
+        .map(|size| Ok((*size, format!("{}/__gen/bytes/{}", base, size).parse()?)))
+        .collect::<Result<Vec<(u64, http::Uri)>, Box<dyn Error>>>()?;
+    info!(
+        "Benchmarking {} with {} connections of {} streams for {} s",
+        base, bench.connections, bench.streams, bench.duration_s
+    );
+
+    let client_crypto = super::certs_configuration::get_client_crypto(&config)?;
+    let cpu_start = cpu_time();
+    let start = Instant::now();
+    let deadline = start + Duration::from_secs(bench.duration_s);
+    let connections = (0..bench.connections).map(|_| {
+        run_connection(
+            &config,
+            client_crypto.clone(),
+            &uris,
+            bench.streams,
+            deadline,
+        )
+    });
+    let mut samples = Vec::new();
+    for result in future::join_all(connections).await {
+        samples.extend(result?);
+    }
+    let elapsed = start.elapsed();
+    let cpu_used = cpu_start.and_then(|cpu_start| Some(cpu_time()? - cpu_start));
+
+    let bytes = samples.iter().map(|sample| sample.bytes).sum();
+    let report = BenchReport {
+        url: base,
+        connections: bench.connections,
+        streams: bench.streams,
+        duration_s: elapsed.as_secs_f64(),
+        requests: samples.len(),
+        bytes,
+        goodput_mbit_s: mbit_s(bytes, elapsed),
+        cpu_time_s: cpu_used.map(|cpu_used| cpu_used.as_secs_f64()),
+        peak_rss_kib: peak_rss_kib(),
+        sizes: sizes
+            .iter()
+            .map(|&size| size_report(size, &samples))
+            .collect(),
+    };
+    print_report(&report, bench.url.is_none());
+    if !config.logs.is_empty() {
+        let path = Path::new(&config.logs).join(REPORT_FILE);
+        fs::write(&path, serde_json::to_string_pretty(&report)?)?;
+        info!("Report written to {:?}", path);
+    }
+    Ok(())
+}
+
+/// It starts a server in the process, with a new self-signed certificate in a temporary
+/// directory, makes the client trust it and returns its URL and the directory.
+async fn start_local_server(config: &mut Config) -> Result<(String, LocalDir), Box<dyn Error>> {
+    let dir = LocalDir(
+        std::env::temp_dir().join(format!("quic-implementation-bench-{}", std::process::id())),
+    );
+    let (www, certs, logs) = (dir.0.join("www"), dir.0.join("certs"), dir.0.join("logs"));
+    for dir in [&www, &certs, &logs] {
+        fs::create_dir_all(dir)?;
+    }
+    let sslkeylogfile = if config.sslkeylogfile.is_empty() {
+        logs.join("keys.log").to_string_lossy().into_owned()
+    } else {
+        config.sslkeylogfile.clone()
+    };
+    let addr = server::spawn_local(&www, &certs, &logs, &sslkeylogfile).await?;
+
+    config.insecure = false;
+    config.pinned_spki.clear();
+    config.ca_file = certs.join("ca.pem").to_string_lossy().into_owned();
+    Ok((format!("https://localhost:{}", addr.port()), dir))
+}
+
+/// The directory of the local server, removed with its content once the benchmark is over.
+struct LocalDir(PathBuf);
+
+impl Drop for LocalDir {
+    fn drop(&mut self) {
+        if let Err(err) = fs::remove_dir_all(&self.0) {
+            info!("Unable to remove {:?}: {}", self.0, err);
+        }
+    }
+}
+
+/// It opens one connection and downloads on `streams` concurrent streams until the deadline.
+async fn run_connection(
+    config: &Config,
+    client_crypto: rustls::ClientConfig,
+    uris: &[(u64, http::Uri)],
+    streams: usize,
+    deadline: Instant,
+) -> Result<Vec<Sample>, Box<dyn Error>> {
+    let (dest, addr) = resolve(&uris[0].1.to_string()).await?;
+    let client_config =
+        make_client_config(client_crypto, &config.quic_versions, &config.transport)?;
+    let mut client_endpoint = make_endpoint(&config.quic_versions, &config.transport)?;
+    client_endpoint.set_default_client_config(client_config);
+    let connecting = client_endpoint.connect(addr, &server_name(&dest, config.insecure)?)?;
+    let new_conn = connect(connecting, false).await?;
+    let (mut driver, send_request) = h3::client::new(h3_quinn::Connection::new(new_conn)).await?;
+    let drive = async move {
+        future::poll_fn(|cx| driver.poll_close(cx)).await?;
+        Ok::<(), Box<dyn Error>>(())
+    };
+
+    let downloads = async move {
+        let workers = (0..streams).map(|worker| {
+            let mut send_request = send_request.clone();
+            async move {
+                let mut samples = Vec::new();
+                // Each stream starts at a different size, so that all the sizes are in flight together
+                let mut next = worker;
+                while Instant::now() < deadline {
+                    let (size, uri) = &uris[next % uris.len()];
+                    next += 1;
+                    let start = Instant::now();
+                    let req = http::Request::builder().uri(uri.clone()).body(())?;
+                    let mut stream = send_request.send_request(req).await?;
+                    stream.finish().await?;
+                    let resp = stream.recv_response().await?;
+                    if !resp.status().is_success() {
+                        Err(format!("{} answered {}", uri, resp.status()))?;
+                    }
+                    let mut bytes = 0;
+                    while let Some(chunk) = stream.recv_data().await? {
+                        bytes += chunk.len() as u64;
+                    }
+                    samples.push(Sample {
+                        size: *size,
+                        bytes,
+                        duration: start.elapsed(),
+                    });
+                }
+                Ok::<_, Box<dyn Error>>(samples)
+            }
+        });
+        let mut samples = Vec::new();
+        for result in future::join_all(workers).await {
+            samples.extend(result?);
+        }
+        Ok::<_, Box<dyn Error>>(samples)
+    };
+
+    let (samples, drive_res) = tokio::join!(downloads, drive);
+    let samples = samples?;
+    drive_res?;
+    client_endpoint.wait_idle().await;
+    Ok(samples)
+}
+
+fn size_report(size: u64, samples: &[Sample]) -> SizeReport {
+    let mut goodputs: Vec<f64> = samples
+        .iter()
+        .filter(|sample| sample.size == size)
+        .map(|sample| mbit_s(sample.bytes, sample.duration))
+        .collect();
+    goodputs.sort_by(f64::total_cmp);
+    SizeReport {
+        size,
+        requests: goodputs.len(),
+        p50_mbit_s: percentile(&goodputs, 50),
+        p90_mbit_s: percentile(&goodputs, 90),
+        p99_mbit_s: percentile(&goodputs, 99),
+    }
+}
+
+fn print_report(report: &BenchReport, local_server: bool) {
+    println!(
+        "{:>12} {:>9} {:>12} {:>12} {:>12}",
+        "size", "requests", "p50 Mbit/s", "p90 Mbit/s", "p99 Mbit/s"
+    );
+    for size in &report.sizes {
+        println!(
+            "{:>12} {:>9} {:>12.2} {:>12.2} {:>12.2}",
+            size.size, size.requests, size.p50_mbit_s, size.p90_mbit_s, size.p99_mbit_s
+        );
+    }
+    println!(
+        "{} requests, {} bytes in {:.2} s: {:.2} Mbit/s",
+        report.requests, report.bytes, report.duration_s, report.goodput_mbit_s
+    );
+    println!(
+        "CPU time {}, peak RSS {}{}",
+        report
+            .cpu_time_s
+            .map_or(String::from("unknown"), |cpu_time| format!(
+                "{:.2} s",
+                cpu_time
+            )),
+        report
+            .peak_rss_kib
+            .map_or(String::from("unknown"), |rss| format!("{} KiB", rss)),
+        if local_server {
+            ", the local server included"
+        } else {
+            ""
+        }
+    );
+}
+
+/// The nearest-rank percentile of sorted values, 0 if there are none.
+fn percentile(sorted: &[f64], p: usize) -> f64 {
+    if sorted.is_empty() {
+        return 0.0;
+    }
+    let rank = (p * sorted.len()).div_ceil(100);
+    sorted[rank.max(1) - 1]
+}
+
+fn mbit_s(bytes: u64, duration: Duration) -> f64 {
+    bytes as f64 * 8.0 / duration.as_secs_f64().max(1e-9) / 1e6
+}
+
+/// The user and system CPU time of the process, from /proc/self/stat.
+fn cpu_time() -> Option<Duration> {
+    let stat = fs::read_to_string("/proc/self/stat").ok()?;
+    // The fields after the command name, which can contain spaces, start with the state
+    let mut fields = stat
+        .get(stat.rfind(')')? + 2..)?
+        .split_whitespace()
+        .skip(11);
+    let user: u64 = fields.next()?.parse().ok()?;
+    let system: u64 = fields.next()?.parse().ok()?;
+    // /proc counts in USER_HZ, which is 100 on Linux
+    Some(Duration::from_millis((user + system) * 10))
+}
+
+/// The peak resident set size of the process, from /proc/self/status.
+fn peak_rss_kib() -> Option<u64> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmHWM:"))?
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse()
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percentiles_use_the_nearest_rank() {
+        let sorted = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0, 10.0];
+        assert_eq!(percentile(&sorted, 0), 1.0);
+        assert_eq!(percentile(&sorted, 50), 5.0);
+        assert_eq!(percentile(&sorted, 95), 10.0);
+        assert_eq!(percentile(&sorted, 100), 10.0);
+        assert_eq!(percentile(&[3.0], 50), 3.0);
+    }
+
+    #[test]
+    fn the_percentile_of_no_value_is_0() {
+        assert_eq!(percentile(&[], 50), 0.0);
+    }
+}
@@ -1,47 +1,53 @@
-use rustls::client::ServerCertVerified;
-use rustls::{Certificate, ServerName};
-use rustls::{ClientConfig, cipher_suite::TLS13_CHACHA20_POLY1305_SHA256};
+use rustls::client::WebPkiVerifier;
+use rustls::ClientConfig;
 use std::error::Error;
 use std::sync::Arc;
-use std::time::SystemTime;
 
 use super::super::commons;
+use super::super::commons::certificates;
+use super::super::commons::tls_config;
+use super::env_parser::Config;
+use super::verification::{self, PinVerifier, YesVerifier};
 
-pub fn get_client_crypto(testcase: &String) -> Result<ClientConfig, Box<dyn Error>> {
-    let tls_config_builder = if testcase == "chacha20"  {
-        let cipher_suites = [TLS13_CHACHA20_POLY1305_SHA256];
-        ClientConfig::builder()
-            .with_cipher_suites(&cipher_suites)
-            .with_safe_default_kx_groups()
-            .with_protocol_versions(&[&rustls::version::TLS13])?
+pub fn get_client_crypto(config: &Config) -> Result<ClientConfig, Box<dyn Error>> {
+    let cipher_suites = tls_config::cipher_suites(&config.cipher_suites, &config.testcase)?;
+    let kx_groups = tls_config::kx_groups(&config.kx_groups)?;
+    println!(
+        "Offered TLS cipher suites: {:?}, key exchange groups: {:?}",
+        cipher_suites
+            .iter()
+            .map(|suite| tls_config::suite_name(suite.suite()))
+            .collect::<Vec<_>>(),
+        kx_groups
+            .iter()
+            .map(|group| tls_config::group_name(group))
+            .collect::<Vec<_>>()
+    );
+    let tls_config_builder = ClientConfig::builder()
+        .with_cipher_suites(&cipher_suites)
+        .with_kx_groups(&kx_groups)
+        .with_protocol_versions(&[&rustls::version::TLS13])?;
+    let tls_config_builder = if config.insecure {
+        println!("Server certificates are not verified");
+        tls_config_builder.with_custom_certificate_verifier(Arc::new(YesVerifier))
+    } else if !config.pinned_spki.is_empty() {
+        let verifier = PinVerifier::new(&config.pinned_spki)?;
+        tls_config_builder.with_custom_certificate_verifier(Arc::new(verifier))
     } else {
-        ClientConfig::builder()
-            .with_safe_default_cipher_suites()
-            .with_safe_default_kx_groups()
-            .with_protocol_versions(&[&rustls::version::TLS13])?
+        // A verifier rather than the roots, so that every branch leaves the builder in one state
+        let verifier = WebPkiVerifier::new(verification::load_roots(&config.ca_file)?, None);
+        tls_config_builder.with_custom_certificate_verifier(Arc::new(verifier))
+    };
+    let mut tls_config = if config.client_cert.is_empty() {
+        tls_config_builder.with_no_client_auth()
+    } else {
+        let cert_chain = certificates::read_certs(&config.client_cert)?;
+        let key = certificates::read_private_key(&config.client_key, &config.key_passphrase_file)?;
+        tls_config_builder.with_single_cert(cert_chain, key)?
     };
-    let mut tls_config = tls_config_builder
-        .with_custom_certificate_verifier(Arc::new(YesVerifier))
-        .with_no_client_auth();
     tls_config.enable_early_data = true;
     tls_config.alpn_protocols = vec![commons::ALPN.into()];
     tls_config.key_log = Arc::new(rustls::KeyLogFile::new());
 
     Ok(tls_config)
 }
-
-struct YesVerifier;
-
-impl rustls::client::ServerCertVerifier for YesVerifier {
-    fn verify_server_cert(
-        &self,
-        _end_entity: &Certificate,
-        _intermediates: &[Certificate],
-        _server_name: &ServerName,
-        _scts: &mut dyn Iterator<Item = &[u8]>,
-        _ocsp_response: &[u8],
-        _now: SystemTime,
-    ) -> Result<ServerCertVerified, rustls::Error> {
-        Ok(ServerCertVerified::assertion())
-    }
-}
@@ -1,29 +1,189 @@
 use serde::Deserialize;
-use std::error::Error;
+use structopt::StructOpt;
 
-fn default_requests() -> String {
-    String::from("")
+use super::super::commons;
+use super::super::commons::certificates;
+use super::super::commons::config;
+use super::super::commons::tls_config;
+use super::verification;
+use super::super::commons::transport_config::TransportParameters;
+use super::super::commons::validation::{self, ConfigError};
+
+/// The command line of the client. Every flag falls back to the environment variable the
+/// interop runner sets, and then to the configuration file, so the runner keeps working
+/// without passing any argument.
+#[derive(StructOpt, Debug)]
+#[structopt(
+    name = "client",
+    about = "HTTP/3 client for the QUIC interop runner",
+    after_help = "EXIT CODES:
+    0    every download succeeded
+    1    invalid configuration, or an error that stopped the run
+    3    a download got a status other than 2xx
+    4    a download failed because of the connection, the stream or a corrupt body
+    5    a download timed out
+  127    unsupported testcase
+When downloads fail in different ways, the highest code is used."
+)]
+struct CliConfig {
+    /// TOML configuration file, overridden by environment variables and flags
+    #[structopt(long, env = "CONFIG_FILE")]
+    config_file: Option<String>,
+    /// File the TLS secrets are logged to, in the NSS Key Log format
+    #[structopt(long, env = "SSLKEYLOGFILE")]
+    sslkeylogfile: Option<String>,
+    /// Directory for the qlog output
+    #[structopt(long, env = "QLOGDIR")]
+    qlogdir: Option<String>,
+    /// Directory for the general logs
+    #[structopt(long, env = "LOGS")]
+    logs: Option<String>,
+    /// Name of the interop testcase
+    #[structopt(long, env = "TESTCASE")]
+    testcase: Option<String>,
+    /// Directory the downloaded files are stored in
+    #[structopt(long, env = "DOWNLOADS")]
+    downloads: Option<String>,
+    /// Space separated list of URLs to download, e.g. https://127.0.0.2:445/xyz
+    #[structopt(long, env = "REQUESTS")]
+    requests: Option<String>,
+    /// Comma separated list of supported QUIC versions, the first one starts the handshake
+    #[structopt(long, env = "QUIC_VERSIONS")]
+    quic_versions: Option<String>,
+    /// TOML file with the transport parameters
+    #[structopt(long, env = "TRANSPORT_CONFIG")]
+    transport_config: Option<String>,
+    /// Accept any server certificate, as needed with the interop runner
+    #[structopt(long)]
+    insecure: bool,
+    /// PEM bundle of the roots to verify the server with, instead of the platform ones
+    #[structopt(long, env = "CA_FILE")]
+    ca_file: Option<String>,
+    /// Comma separated base64 SHA-256 hashes of the accepted server public keys
+    #[structopt(long, env = "PINNED_SPKI")]
+    pinned_spki: Option<String>,
+    /// PEM certificate chain presented to servers asking for one
+    #[structopt(long, env = "CLIENT_CERT")]
+    client_cert: Option<String>,
+    /// PEM private key of the client certificate
+    #[structopt(long, env = "CLIENT_KEY")]
+    client_key: Option<String>,
+    /// File whose first line is the passphrase of an encrypted client key
+    #[structopt(long, env = "KEY_PASSPHRASE_FILE")]
+    key_passphrase_file: Option<String>,
+    /// Comma separated TLS 1.3 cipher suites in order of preference, e.g. TLS_AES_256_GCM_SHA384
+    #[structopt(long, env = "CIPHER_SUITES")]
+    cipher_suites: Option<String>,
+    /// Comma separated key exchange groups in order of preference, e.g. x25519,secp256r1
+    #[structopt(long, env = "KX_GROUPS")]
+    kx_groups: Option<String>,
+    /// Number of times a download is retried after a transport error, a timeout or a 408, 425,
+    /// 429, 502, 503 or 504 status
+    #[structopt(long, env = "RETRIES")]
+    retries: Option<u32>,
+    /// Seconds an attempt to download a file may take
+    #[structopt(long, env = "REQUEST_TIMEOUT_S")]
+    request_timeout_s: Option<u64>,
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(StructOpt, Debug)]
+enum Command {
+    /// Measure the goodput of repeated downloads instead of fetching REQUESTS
+    Bench(BenchConfig),
+}
+
+/// The options of the bench subcommand.
+#[derive(StructOpt, Debug, Clone)]
+pub struct BenchConfig {
+    /// Base URL of a server with the /__gen/ routes, e.g. https://localhost:4433; without it a
+    /// server is started in the process on loopback
+    #[structopt(long)]
+    pub url: Option<String>,
+    /// Comma separated payload sizes in bytes, with an optional K, M or G suffix
+    #[structopt(long, default_value = "1M")]
+    pub sizes: String,
+    /// Number of connections
+    #[structopt(long, default_value = "1")]
+    pub connections: usize,
+    /// Number of concurrent request streams on each connection
+    #[structopt(long, default_value = "1")]
+    pub streams: usize,
+    /// Seconds the downloads are repeated for
+    #[structopt(long, default_value = "10")]
+    pub duration_s: u64,
+}
+
+/// The content of the configuration file. Every key is optional and is overridden by the
+/// corresponding environment variable or flag.
+///
+/// ```toml
+/// testcase = "transfer"
+/// downloads = "./downloads"
+/// requests = ["https://localhost:443/index.html"]
+///
+/// [quic]
+/// versions = "0x00000001"
+///
+/// [tls]
+/// sslkeylogfile = "./tmp/ssl_key_log"
+/// ca_file = "./certs/ca.pem"
+/// client_cert = "./certs/alice.pem"
+/// client_key = "./certs/alice.key"
+/// key_passphrase_file = "./certs/alice.pass"
+/// cipher_suites = ["TLS_AES_256_GCM_SHA384"]
+/// kx_groups = ["x25519"]
+///
+/// [logging]
+/// logs = "./tmp/logs"
+///
+/// [download]
+/// retries = 2
+/// timeout_s = 60
+///
+/// [transport]
+/// stream_receive_window = 5120000
+/// ```
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+struct FileConfig {
+    testcase: Option<String>,
+    downloads: Option<String>,
+    requests: Option<Vec<String>>,
+    quic: config::QuicSection,
+    tls: TlsSection,
+    logging: LoggingSection,
+    download: DownloadSection,
+    transport: TransportParameters,
 }
 
-fn mucco() -> String {
-    String::from("")
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+struct TlsSection {
+    sslkeylogfile: Option<String>,
+    insecure: Option<bool>,
+    ca_file: Option<String>,
+    pinned_spki: Option<Vec<String>>,
+    client_cert: Option<String>,
+    client_key: Option<String>,
+    key_passphrase_file: Option<String>,
+    cipher_suites: Option<Vec<String>>,
+    kx_groups: Option<Vec<String>>,
 }
 
-#[derive(Deserialize, Debug)]
-struct EnvConfig {
-    sslkeylogfile: String,
-    #[serde(default = "mucco")]
-    qlogdir: String,
-    logs: String,
-    testcase: String,
-    downloads: String,
-    #[serde(default="default_requests")]
-    requests: String,
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+struct LoggingSection {
+    logs: Option<String>,
+    qlogdir: Option<String>,
 }
 
-fn fetch_env() -> Result<EnvConfig, Box<dyn Error>> {
-    let config = envy::from_env()?;
-    Ok(config)
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+struct DownloadSection {
+    retries: Option<u32>,
+    timeout_s: Option<u64>,
 }
 
 #[derive(Debug)]
@@ -47,30 +207,290 @@ pub struct Config {
     /// A space separated list of requests a client should execute one by one. (e.g.,
     /// https://127.0.0.2:445/xyz)
     pub requests: Vec<String>,
+    /// A comma separated list of the QUIC versions the client supports (e.g. 0x00000001,0xff00001d).
+    /// The first one is used to start the handshake. If it is empty, QUIC v1 is used.
+    pub quic_versions: Vec<u32>,
+    /// The transport parameters: the [transport] section of the configuration file, then the
+    /// TOML file in TRANSPORT_CONFIG and the TP_* environment variables
+    /// (e.g. TP_STREAM_RECEIVE_WINDOW).
+    pub transport: TransportParameters,
+    /// If it is set, the server certificate is not verified at all. The interop runner needs it,
+    /// as its certificates are signed by a throwaway CA.
+    pub insecure: bool,
+    /// It contains the path to a PEM bundle of the roots the server certificate has to chain to.
+    /// If it is empty, the roots of the platform are used.
+    pub ca_file: String,
+    /// The base64 SHA-256 hashes of the DER SubjectPublicKeyInfo the server may use. If it is not
+    /// empty, the leaf, or a certificate of its chain that signed it, has to have one of them,
+    /// whatever its issuer, and the leaf has to be valid for the host.
+    pub pinned_spki: Vec<String>,
+    /// It contains the path to the PEM certificate chain sent to servers asking for a client
+    /// certificate. If it is empty, the client stays anonymous.
+    pub client_cert: String,
+    /// It contains the path to the PEM private key of client_cert.
+    pub client_key: String,
+    /// It contains the path to a file whose first line is the passphrase of client_key, if it
+    /// is an encrypted PKCS#8 key.
+    pub key_passphrase_file: String,
+    /// The TLS 1.3 cipher suites, in order of preference. If it is empty, the rustls defaults
+    /// are used, or only ChaCha20 for the chacha20 testcase.
+    pub cipher_suites: Vec<String>,
+    /// The key exchange groups, in order of preference. If it is empty, all the rustls groups
+    /// are used.
+    pub kx_groups: Vec<String>,
+    /// The number of times a download is retried when it failed in a way another attempt may
+    /// fix, waiting 500 ms before the first retry and twice as long before each following one.
+    /// It is 2 by default.
+    pub retries: u32,
+    /// The seconds an attempt to download a file may take, the connection included when there is
+    /// one per file. It is 60 by default.
+    pub request_timeout_s: u64,
+    /// The options of the bench subcommand, if it was given. The benchmark doesn't need the
+    /// interop runner variables, so testcase, downloads, requests and sslkeylogfile are optional
+    /// then.
+    pub bench: Option<BenchConfig>,
 }
 
 impl Config {
-    /// It fetches the envinromnent and returns a Config struct.
-    pub fn new() -> Config {
-        let config = fetch_env().expect("Error in parsing the environment");
-        let testcases = vec!["handshake", "transfer", "multihandshake", "chacha20", "retry", "resumption", "transportparameter"];
-        if !testcases.into_iter().any(|el| String::from(el) == config.testcase) {
-            println!("exited with code 127");
-            std::process::exit(127);
-        }
-        let requests = config
-            .requests
-            .split_whitespace()
-            .map(|word| word.to_string())
-            .collect();
-        // TODO: add validation of the config
-        Config {
-            sslkeylogfile: config.sslkeylogfile,
-            qlogdir: config.qlogdir,
-            logs: config.logs,
-            testcase: config.testcase,
-            downloads: config.downloads,
+    /// It reads the configuration with this precedence: command line flags, environment
+    /// variables, configuration file, defaults. It returns a validated Config struct, or all
+    /// the problems found at once.
+    pub fn new() -> Result<Config, ConfigError> {
+        let cli = CliConfig::from_args();
+        let mut problems = Vec::new();
+        let file: FileConfig = match &cli.config_file {
+            Some(path) => commons::read_toml_file(path).unwrap_or_else(|e| {
+                problems.push(format!("`--config-file`: {}", e));
+                FileConfig::default()
+            }),
+            None => FileConfig::default(),
+        };
+
+        let bench = cli.command.map(|Command::Bench(bench)| bench);
+        let is_bench = bench.is_some();
+        // A benchmark doesn't use the settings of the interop runner
+        let mut required = |value: Option<String>, key: &str, flag: &str| {
+            if is_bench {
+                value.unwrap_or_default()
+            } else {
+                config::required(value, key, flag, &mut problems)
+            }
+        };
+        let sslkeylogfile = required(cli.sslkeylogfile.or(file.tls.sslkeylogfile), "tls.sslkeylogfile", "sslkeylogfile");
+        let logs = required(cli.logs.or(file.logging.logs), "logging.logs", "logs");
+        let testcase = required(cli.testcase.or(file.testcase), "testcase", "testcase");
+        let downloads = required(cli.downloads.or(file.downloads), "downloads", "downloads");
+
+        let testcases = vec!["handshake", "transfer", "multihandshake", "chacha20", "retry", "resumption", "transportparameter", "rebind-port", "rebind-addr", "connectionmigration", "ecn", "versionnegotiation", "goodput", "optimize", "zerortt"];
+        if !testcase.is_empty() && !testcases.into_iter().any(|el| el == testcase) {
+            return Err(ConfigError::UnsupportedTestcase(testcase));
+        }
+        let requests = match cli.requests {
+            Some(requests) => requests
+                .split_whitespace()
+                .map(|word| word.to_string())
+                .collect(),
+            None => file.requests.unwrap_or_default(),
+        };
+        let quic_versions = commons::parse_versions(&cli.quic_versions.or(file.quic.versions).unwrap_or_default())
+            .unwrap_or_else(|e| {
+                problems.push(format!("`quic.versions`: {}", e));
+                Vec::new()
+            });
+        let transport = TransportParameters::load(&cli.transport_config.unwrap_or_default(), file.transport.clone())
+            .unwrap_or_else(|e| {
+                problems.push(format!("`transport`: {}", e));
+                file.transport
+            });
+
+        let insecure = cli.insecure || file.tls.insecure.unwrap_or(false);
+        let pinned_spki = config::split_list(cli.pinned_spki, file.tls.pinned_spki);
+
+        let config = Config {
+            sslkeylogfile,
+            qlogdir: cli.qlogdir.or(file.logging.qlogdir).unwrap_or_default(),
+            logs,
+            testcase,
+            downloads,
             requests,
+            quic_versions,
+            transport,
+            insecure,
+            ca_file: cli.ca_file.or(file.tls.ca_file).unwrap_or_default(),
+            pinned_spki,
+            client_cert: cli.client_cert.or(file.tls.client_cert).unwrap_or_default(),
+            client_key: cli.client_key.or(file.tls.client_key).unwrap_or_default(),
+            key_passphrase_file: cli.key_passphrase_file.or(file.tls.key_passphrase_file).unwrap_or_default(),
+            cipher_suites: config::split_list(cli.cipher_suites, file.tls.cipher_suites),
+            kx_groups: config::split_list(cli.kx_groups, file.tls.kx_groups),
+            retries: cli.retries.or(file.download.retries).unwrap_or(2),
+            request_timeout_s: cli.request_timeout_s.or(file.download.timeout_s).unwrap_or(60),
+            bench,
+        };
+        if let Err(ConfigError::Invalid(found)) = config.validate() {
+            problems.extend(found);
+        }
+        validation::into_result(problems)?;
+
+        // rustls::KeyLogFile reads the variable by itself, so a value given on the command line
+        // or in the configuration file has to be exported
+        std::env::set_var("SSLKEYLOGFILE", &config.sslkeylogfile);
+        Ok(config)
+    }
+
+    /// It checks that the directories can be written, the requests are https URLs with a host
+    /// and the certificate verification settings are usable. All the problems are reported
+    /// together.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let mut problems = Vec::new();
+        if !self.downloads.is_empty() {
+            validation::check_writable_dir("downloads", &self.downloads, &mut problems);
+        }
+        if !self.logs.is_empty() {
+            validation::check_writable_dir("logging.logs", &self.logs, &mut problems);
+        }
+        if !self.qlogdir.is_empty() {
+            validation::check_writable_dir("logging.qlogdir", &self.qlogdir, &mut problems);
+        }
+        if !self.sslkeylogfile.is_empty() {
+            validation::check_writable_file("tls.sslkeylogfile", &self.sslkeylogfile, &mut problems);
+        }
+        if let Some(bench) = &self.bench {
+            bench.check(&mut problems);
+        } else if self.requests.is_empty() {
+            problems.push(String::from("`requests`: no URL to download"));
+        }
+        for request in &self.requests {
+            match request.parse::<http::Uri>() {
+                Ok(uri) => {
+                    if uri.scheme() != Some(&http::uri::Scheme::HTTPS) {
+                        problems.push(format!("`requests`: {} is not an https URL", request));
+                    }
+                    match uri.host() {
+                        Some(host) if !host.is_empty() => {}
+                        _ => problems.push(format!("`requests`: {} has no host", request)),
+                    }
+                }
+                Err(e) => problems.push(format!("`requests`: {} is not a URL: {}", request, e)),
+            }
+        }
+        if self.insecure && (!self.ca_file.is_empty() || !self.pinned_spki.is_empty()) {
+            problems.push(String::from("`tls.insecure`: cannot be combined with `tls.ca_file` or `tls.pinned_spki`"));
+        }
+        if !self.ca_file.is_empty() && !self.pinned_spki.is_empty() {
+            problems.push(String::from("`tls.pinned_spki`: cannot be combined with `tls.ca_file`"));
+        }
+        if !self.ca_file.is_empty() {
+            if let Err(e) = verification::load_roots(&self.ca_file) {
+                problems.push(format!("`tls.ca_file`: no usable root in {}: {}", self.ca_file, e));
+            }
         }
+        for pin in &self.pinned_spki {
+            if let Err(e) = verification::decode_pin(pin) {
+                problems.push(format!("`tls.pinned_spki`: {}", e));
+            }
+        }
+        match (self.client_cert.is_empty(), self.client_key.is_empty()) {
+            (true, true) => {}
+            (false, true) => problems.push(String::from("`tls.client_key`: missing, required by client_cert")),
+            (true, false) => problems.push(String::from("`tls.client_cert`: missing, required by client_key")),
+            (false, false) => {
+                if let Err(e) = certificates::read_certs(&self.client_cert) {
+                    problems.push(format!("`tls.client_cert`: {}", e));
+                }
+                if let Err(e) = certificates::read_private_key(&self.client_key, &self.key_passphrase_file) {
+                    problems.push(format!("`tls.client_key`: {}", e));
+                }
+            }
+        }
+        match tls_config::cipher_suites(&self.cipher_suites, &self.testcase) {
+            Ok(suites) if suites.is_empty() => problems.push(String::from("`tls.cipher_suites`: no cipher suite left")),
+            Ok(_) => {}
+            Err(e) => problems.push(format!("`tls.cipher_suites`: {}", e)),
+        }
+        if let Err(e) = tls_config::kx_groups(&self.kx_groups) {
+            problems.push(format!("`tls.kx_groups`: {}", e));
+        }
+        if self.request_timeout_s == 0 {
+            problems.push(String::from("`download.timeout_s`: must be greater than 0"));
+        }
+        self.transport.check(&mut problems);
+        validation::into_result(problems)
+    }
+}
+
+impl BenchConfig {
+    /// The payload sizes in bytes.
+    pub fn payload_sizes(&self) -> Result<Vec<u64>, String> {
+        config::split_list(Some(self.sizes.clone()), None)
+            .iter()
+            .map(|size| {
+                let (digits, unit) = match size.to_uppercase().chars().last() {
+                    Some('K') => (&size[..size.len() - 1], 1 << 10),
+                    Some('M') => (&size[..size.len() - 1], 1 << 20),
+                    Some('G') => (&size[..size.len() - 1], 1 << 30),
+                    _ => (&size[..], 1),
+                };
+                digits
+                    .parse::<u64>()
+                    .ok()
+                    .and_then(|n| n.checked_mul(unit))
+                    .ok_or(format!("{} is not a size", size))
+            })
+            .collect()
+    }
+
+    fn check(&self, problems: &mut Vec<String>) {
+        match self.payload_sizes() {
+            Ok(sizes) if sizes.is_empty() => problems.push(String::from("`bench.sizes`: no payload size")),
+            Ok(sizes) if sizes.iter().any(|&size| size > 1 << 30) => problems.push(String::from("`bench.sizes`: the server generates at most 1G")),
+            Ok(_) => {}
+            Err(e) => problems.push(format!("`bench.sizes`: {}", e)),
+        }
+        if self.connections == 0 {
+            problems.push(String::from("`bench.connections`: must be greater than 0"));
+        }
+        if self.streams == 0 {
+            problems.push(String::from("`bench.streams`: must be greater than 0"));
+        }
+        if self.duration_s == 0 {
+            problems.push(String::from("`bench.duration_s`: must be greater than 0"));
+        }
+        if let Some(url) = &self.url {
+            if !url.starts_with("https://") || url.parse::<http::Uri>().is_err() {
+                problems.push(format!("`bench.url`: {} is not an https URL", url));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bench(sizes: &str) -> BenchConfig {
+        BenchConfig {
+            url: None,
+            sizes: String::from(sizes),
+            connections: 1,
+            streams: 1,
+            duration_s: 1,
+        }
+    }
+
+    #[test]
+    fn payload_sizes_take_binary_suffixes() {
+        assert_eq!(
+            bench("100, 4k,1M,2G").payload_sizes(),
+            Ok(vec![100, 4 << 10, 1 << 20, 2 << 30])
+        );
+    }
+
+    #[test]
+    fn invalid_payload_sizes_are_rejected() {
+        assert!(bench("1T").payload_sizes().is_err());
+        assert!(bench("M").payload_sizes().is_err());
+        assert!(bench("-1").payload_sizes().is_err());
+        assert!(bench("18446744073709551615G").payload_sizes().is_err());
     }
 }
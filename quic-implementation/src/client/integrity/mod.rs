@@ -0,0 +1,230 @@
+use std::error::Error;
+
+use ring::digest::{Context, SHA256};
+
+use super::outcome::HttpStatus;
+
+/// A SHA-256 expected for the body, with the name of the field it came from.
+type FieldDigest = (&'static str, Vec<u8>);
+
+/// It checks a response body as it arrives against the Content-Length and the SHA-256 of the
+/// Digest (RFC 3230) and Repr-Digest (RFC 9530) headers and trailers. Other digest algorithms
+/// are ignored.
+pub struct Integrity {
+    expected_len: Option<u64>,
+    received: u64,
+    digests: Vec<FieldDigest>,
+    context: Context,
+}
+
+impl Integrity {
+    /// It fails for a status other than 2xx, whose body is not the requested file, and for
+    /// headers that can't be parsed.
+    pub fn new(resp: &http::Response<()>) -> Result<Integrity, Box<dyn Error>> {
+        if !resp.status().is_success() {
+            Err(HttpStatus(resp.status()))?;
+        }
+        let headers = resp.headers();
+        let expected_len = match headers.get(http::header::CONTENT_LENGTH) {
+            Some(value) => Some(
+                value
+                    .to_str()
+                    .ok()
+                    .and_then(|value| value.trim().parse().ok())
+                    .ok_or_else(|| format!("invalid content-length {:?}", value))?,
+            ),
+            None => None,
+        };
+        Ok(Integrity {
+            expected_len,
+            received: 0,
+            digests: digests(headers)?,
+            context: Context::new(&SHA256),
+        })
+    }
+
+    /// It adds the digests of the trailers, which arrive once the body is received. Every body
+    /// is hashed, as they can only be known at the end.
+    pub fn trailers(&mut self, trailers: &http::HeaderMap) -> Result<(), Box<dyn Error>> {
+        self.digests.extend(digests(trailers)?);
+        Ok(())
+    }
+
+    pub fn update(&mut self, chunk: &[u8]) -> Result<(), Box<dyn Error>> {
+        self.received += chunk.len() as u64;
+        if let Some(expected_len) = self.expected_len {
+            if self.received > expected_len {
+                Err(format!(
+                    "received more than the {} bytes of content-length",
+                    expected_len
+                ))?;
+            }
+        }
+        self.context.update(chunk);
+        Ok(())
+    }
+
+    /// It checks the whole body once it is received.
+    pub fn finish(self) -> Result<(), Box<dyn Error>> {
+        if let Some(expected_len) = self.expected_len {
+            if self.received != expected_len {
+                Err(format!(
+                    "received {} bytes instead of the {} of content-length",
+                    self.received, expected_len
+                ))?;
+            }
+        }
+        if self.digests.is_empty() {
+            return Ok(());
+        }
+        let actual = self.context.finish();
+        for (name, expected) in &self.digests {
+            if actual.as_ref() != expected.as_slice() {
+                Err(format!(
+                    "the SHA-256 of the body doesn't match the {} header",
+                    name
+                ))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The SHA-256 digests of the Digest and Repr-Digest fields, with the name of their field.
+fn digests(headers: &http::HeaderMap) -> Result<Vec<FieldDigest>, Box<dyn Error>> {
+    let mut digests = Vec::new();
+    for (header, structured) in [("digest", false), ("repr-digest", true)] {
+        for value in headers.get_all(header) {
+            for entry in dictionary(value) {
+                if let Some(digest) = sha256_entry(header, &entry, structured)? {
+                    digests.push((header, digest));
+                }
+            }
+        }
+    }
+    Ok(digests)
+}
+
+/// The `name=value` entries of a comma separated header value.
+fn dictionary(value: &http::HeaderValue) -> Vec<(String, String)> {
+    String::from_utf8_lossy(value.as_bytes())
+        .split(',')
+        .filter_map(|entry| {
+            let (name, value) = entry.split_once('=')?;
+            Some((name.trim().to_lowercase(), value.trim().to_string()))
+        })
+        .collect()
+}
+
+/// The decoded SHA-256 of a digest entry, `None` for another algorithm. Repr-Digest values are
+/// byte sequences of structured fields, wrapped in colons.
+fn sha256_entry(
+    header: &str,
+    (algorithm, value): &(String, String),
+    structured: bool,
+) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+    if algorithm != "sha-256" {
+        return Ok(None);
+    }
+    let encoded = if structured {
+        value
+            .strip_prefix(':')
+            .and_then(|value| value.strip_suffix(':'))
+            .ok_or_else(|| format!("invalid {} value {}", header, value))?
+    } else {
+        value
+    };
+    let digest = base64::decode(encoded)
+        .map_err(|e| format!("invalid {} value {}: {}", header, value, e))?;
+    if digest.len() != SHA256.output_len {
+        Err(format!("invalid {} value {}: not a SHA-256", header, value))?;
+    }
+    Ok(Some(digest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The SHA-256 of "hello", in base64.
+    const HELLO_SHA256: &str = "LPJNul+wow4m6DsqxbninhsWHlwfp0JecwQzYpOLmCQ=";
+
+    fn response(headers: &[(&str, &str)]) -> http::Response<()> {
+        let mut builder = http::Response::builder();
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+        builder.body(()).unwrap()
+    }
+
+    fn check(headers: &[(&str, &str)], chunks: &[&[u8]]) -> Result<(), Box<dyn Error>> {
+        let mut integrity = Integrity::new(&response(headers))?;
+        for chunk in chunks {
+            integrity.update(chunk)?;
+        }
+        integrity.finish()
+    }
+
+    #[test]
+    fn a_body_matching_its_headers_is_accepted() {
+        let digest = format!("sha-256={}", HELLO_SHA256);
+        let repr_digest = format!("sha-256=:{}:", HELLO_SHA256);
+        let headers = [
+            ("content-length", "5"),
+            ("digest", digest.as_str()),
+            ("repr-digest", repr_digest.as_str()),
+        ];
+        assert!(check(&headers, &[b"he", b"llo"]).is_ok());
+    }
+
+    #[test]
+    fn a_digest_can_come_in_the_trailers() {
+        let mut integrity = Integrity::new(&response(&[("trailer", "repr-digest")])).unwrap();
+        integrity.update(b"hello").unwrap();
+        let mut trailers = http::HeaderMap::new();
+        let digest = format!("sha-256=:{}:", HELLO_SHA256);
+        trailers.insert("repr-digest", digest.parse().unwrap());
+        integrity.trailers(&trailers).unwrap();
+        assert!(integrity.finish().is_ok());
+
+        let mut integrity = Integrity::new(&response(&[])).unwrap();
+        integrity.update(b"hellO").unwrap();
+        integrity.trailers(&trailers).unwrap();
+        assert!(integrity.finish().is_err());
+    }
+
+    #[test]
+    fn a_body_without_headers_is_accepted() {
+        assert!(check(&[], &[b"anything"]).is_ok());
+    }
+
+    #[test]
+    fn a_different_body_is_rejected() {
+        let digest = format!("sha-256={}", HELLO_SHA256);
+        assert!(check(&[("digest", digest.as_str())], &[b"hellO"]).is_err());
+    }
+
+    #[test]
+    fn other_algorithms_are_ignored() {
+        assert!(check(&[("digest", "md5=XUFAKrxLKna5cZ2REBfFkg==")], &[b"hello"]).is_ok());
+    }
+
+    #[test]
+    fn the_length_has_to_match_content_length() {
+        assert!(check(&[("content-length", "6")], &[b"hello"]).is_err());
+        assert!(check(&[("content-length", "4")], &[b"hello"]).is_err());
+    }
+
+    #[test]
+    fn invalid_headers_are_rejected() {
+        assert!(Integrity::new(&response(&[("content-length", "five")])).is_err());
+        assert!(Integrity::new(&response(&[("digest", "sha-256=not base64")])).is_err());
+        assert!(Integrity::new(&response(&[("repr-digest", "sha-256=AAAA")])).is_err());
+    }
+
+    #[test]
+    fn an_error_status_is_rejected() {
+        let resp = http::Response::builder().status(404).body(()).unwrap();
+        assert!(Integrity::new(&resp).is_err());
+    }
+}
@@ -0,0 +1,32 @@
+use std::error::Error;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+
+use h3_quinn::quinn;
+use tracing::info;
+
+use super::super::commons;
+
+/// It tells if the given testcase requires the client to migrate during the transfer.
+pub fn is_migration_testcase(testcase: &str) -> bool {
+    commons::MIGRATION_TESTCASES.contains(&testcase)
+}
+
+/// It binds a new UDP socket on a fresh port and switches the endpoint (and all its connections)
+/// to it. quinn then sends from the new address and the server validates the new path with
+/// PATH_CHALLENGE/PATH_RESPONSE before moving the connection to it.
+///
+/// quinn 0.8 ignores the `preferred_address` transport parameter, so for `connectionmigration`
+/// this is an active migration of the client's own address towards the same server address.
+pub fn rebind(endpoint: &quinn::Endpoint) -> Result<SocketAddr, Box<dyn Error>> {
+    let old_addr = endpoint.local_addr()?;
+    let bind_addr = if old_addr.is_ipv6() {
+        SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), 0)
+    } else {
+        SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0)
+    };
+    let socket = UdpSocket::bind(bind_addr)?;
+    endpoint.rebind(socket)?;
+    let new_addr = endpoint.local_addr()?;
+    info!("Migrated from {:?} to {:?}", old_addr, new_addr);
+    Ok(new_addr)
+}
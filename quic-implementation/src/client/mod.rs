@@ -1,14 +1,30 @@
-use std::net::ToSocketAddrs;
-use std::path::Path;
+use std::error::Error;
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use futures::future;
 use h3_quinn::quinn;
 use tokio::{self, io::AsyncWriteExt};
 use tracing::info;
 
+use super::commons;
+use super::commons::transport_config::TransportParameters;
+use integrity::Integrity;
+pub use outcome::DownloadsFailed;
+use outcome::{Outcome, Summary};
+use report::{ConnectionReport, Report, RequestReport, RequestTimer};
+
+mod bench;
 mod certs_configuration;
 mod env_parser;
+mod integrity;
+mod migration;
+mod outcome;
+mod report;
+mod verification;
+mod version_negotiation;
 
 pub async fn run_client() -> Result<(), Box<dyn std::error::Error>> {
     tracing_subscriber::fmt()
@@ -17,146 +33,416 @@ pub async fn run_client() -> Result<(), Box<dyn std::error::Error>> {
         .with_writer(std::io::stderr)
         .init();
 
-    let config = env_parser::Config::new();
+    let mut config = env_parser::Config::new()?;
+    println!("{:#?}", config);
+
+    if let Some(bench) = config.bench.clone() {
+        return bench::run(config, bench).await;
+    }
 
     println!("There are {}", config.requests.len());
 
-    let testcase = config.testcase;
+    let testcase = config.testcase.clone();
+    let logs = config.logs.clone();
+    let mut report = Report::new(&testcase);
+    let run_start = Instant::now();
+    let mut summary = Summary::default();
 
-    if vec!["transportparameter", "transfer", "optimize", "goodput"]
-        .iter()
-        .any(|&el| el == testcase)
+    if testcase == "versionnegotiation" {
+        let (_, addr) = resolve(&config.requests[0]).await?;
+        let offered = version_negotiation::probe(addr).await?;
+        let version = version_negotiation::choose(&offered, &config.quic_versions)?;
+        info!("Continuing with version {}", commons::version_name(version));
+        config.quic_versions.retain(|&v| v != version);
+        config.quic_versions.insert(0, version);
+    }
+
+    if [
+        "transportparameter",
+        "transfer",
+        "optimize",
+        "goodput",
+        "ecn",
+        "zerortt",
+    ]
+    .contains(&testcase.as_str())
+        || migration::is_migration_testcase(&testcase)
     {
-        let uri = config.requests[0].clone();
-        let dest = uri.parse::<http::Uri>()?;
-        if dest.scheme() != Some(&http::uri::Scheme::HTTPS) {
-            Err("destination scheme must be 'https'")?;
-        }
-        let auth = dest
-            .authority()
-            .ok_or("destination must have a host")?
-            .clone();
-        let port = auth.port_u16().unwrap_or(443);
-        let addr = match tokio::net::lookup_host((auth.host(), port)).await {
-            Ok(mut addr) => addr.next().unwrap(),
-            Err(_) => (auth.host(), port).to_socket_addrs()?.next().unwrap(),
-        };
-        info!("DNS Lookup for {:?}: {:?}", dest, addr);
-        let client_crypto = certs_configuration::get_client_crypto(&testcase)?;
-        let client_config = quinn::ClientConfig::new(Arc::new(client_crypto));
-        // let client_config_transport = Arc::get_mut(&mut client_config.transport).unwrap();
-        // client_config_transport.stream_receive_window(5120000u32.into());
-        // client_config_transport.receive_window(5120000u32.into());
-        // client_config_transport.packet_threshold(10);
-        // client_config_transport.datagram_receive_buffer_size(Some(5120000));
-        // client_config_transport.max_idle_timeout(Some(VarInt::from_u32(200).into()));
-        let mut client_endpoint = h3_quinn::quinn::Endpoint::client("[::]:0".parse().unwrap())?;
+        let (dest, addr) = resolve(&config.requests[0]).await?;
+        let server_name = server_name(&dest, config.insecure)?;
+        let client_crypto = certs_configuration::get_client_crypto(&config)?;
+        let client_config =
+            make_client_config(client_crypto, &config.quic_versions, &config.transport)?;
+        let mut client_endpoint = make_endpoint(&config.quic_versions, &config.transport)?;
         client_endpoint.set_default_client_config(client_config);
-        let quinn_conn =
-            h3_quinn::Connection::new(client_endpoint.connect(addr, "localhost")?.await?);
-        info!("QUIC connected ...");
-        let (mut driver, send_request) = h3::client::new(quinn_conn).await?;
-        let drive = async move {
-            future::poll_fn(|cx| driver.poll_close(cx)).await?;
-            Ok::<(), Box<dyn std::error::Error>>(())
-        };
-        let downloads = config.downloads.clone();
-
-        let request = async move {
-            for uri in config.requests {
-                // generic h3
-                let dest = uri.parse::<http::Uri>()?;
-                let requested_path = String::from(dest.path());
-                let downloads = downloads.clone();
-                let mut send_request = send_request.clone();
-                info!("Sending request ...");
-                let req = http::Request::builder().uri(dest).body(())?;
-                let mut stream = send_request.send_request(req).await?;
-                stream.finish().await?;
-                info!("Receiving response ...");
-                let resp = stream.recv_response().await?;
-                info!("Response: {:?} {}", resp.version(), resp.status());
-                info!("Headers: {:#?}", resp.headers());
-                let downloads_path = Path::new(&downloads);
-                let requested_path = requested_path.split_at(1).1;
-                let requested_path = downloads_path.join(requested_path);
-                info!("Requested file path is: {:#?}", requested_path);
-                let mut out = tokio::fs::File::create(&requested_path).await?;
-                while let Some(chunk) = stream.recv_data().await? {
-                    out.write(&chunk).await.expect("write");
+        let timeout = Duration::from_secs(config.request_timeout_s);
+        let mut migrated = !migration::is_migration_testcase(&testcase);
+        // The connection the downloads share. The first attempt opens it, and so does the one
+        // following a transport error or a timeout, as the connection may be gone
+        let mut session = None;
+
+        for (i, uri) in config.requests.iter().enumerate() {
+            let dest = uri.parse::<http::Uri>()?;
+            let requested_path = download_path(&config.downloads, &dest);
+            let mut attempts = 0;
+            let outcome = loop {
+                attempts += 1;
+                let mut timer = RequestTimer::start(uri);
+                let attempt = async {
+                    if session.is_none() {
+                        let handshake_start = Instant::now();
+                        let connecting = client_endpoint.connect(addr, &server_name)?;
+                        let new_conn = connect(connecting, testcase == "zerortt").await?;
+                        let handshake = handshake_start.elapsed();
+                        let connection = new_conn.connection.clone();
+                        let quinn_conn = h3_quinn::Connection::new(new_conn);
+                        info!(
+                            "QUIC connected using the version of the first Initial, {}, congestion controller: {} ...",
+                            commons::version_name(first_version(&config.quic_versions)),
+                            config.transport.congestion_controller_name()
+                        );
+                        let (mut driver, send_request) = h3::client::new(quinn_conn).await?;
+                        let driver = tokio::spawn(async move {
+                            if let Err(err) = future::poll_fn(|cx| driver.poll_close(cx)).await {
+                                info!("Connection closed: {}", err);
+                            }
+                        });
+                        session = Some(Session {
+                            handshake,
+                            connection,
+                            send_request,
+                            driver,
+                            requests: Vec::new(),
+                        });
+                    }
+                    // generic h3
+                    let mut send_request = session
+                        .as_ref()
+                        .map(|session| session.send_request.clone())
+                        .ok_or("not connected")?;
+                    info!("Sending request ...");
+                    let req = http::Request::builder().uri(dest.clone()).body(())?;
+                    let mut stream = send_request.send_request(req).await?;
+                    stream.finish().await?;
+                    info!("Receiving response ...");
+                    let resp = stream.recv_response().await?;
+                    timer.response(resp.status());
+                    info!("Response: {:?} {}", resp.version(), resp.status());
+                    info!("Headers: {:#?}", resp.headers());
+                    let mut integrity = Integrity::new(&resp)?;
+                    info!("Requested file path is: {:#?}", requested_path);
+                    let mut out = tokio::fs::File::create(&requested_path).await?;
+                    while let Some(chunk) = stream.recv_data().await? {
+                        integrity.update(&chunk)?;
+                        timer.data(chunk.len());
+                        out.write_all(&chunk).await?;
+                        if !migrated {
+                            migration::rebind(&client_endpoint)?;
+                            migrated = true;
+                        }
+                    }
+                    out.flush().await?;
+                    if let Some(trailers) = stream.recv_trailers().await? {
+                        integrity.trailers(&trailers)?;
+                    }
+                    integrity.finish()?;
+                    info!("File created");
+                    Ok::<(), Box<dyn Error>>(())
+                };
+                let outcome = Outcome::of(timeout, attempt).await;
+                if let Some(session) = session.as_mut() {
+                    session.requests.push(timer.finish());
+                }
+                if matches!(outcome, Outcome::TransportError(_) | Outcome::Timeout) {
+                    if let Some(session) = session.take() {
+                        session.close(true, &mut report).await;
+                    }
+                }
+                if !outcome.is_success() {
+                    remove_partial(&requested_path).await;
+                }
+                if attempts > config.retries || !outcome.is_retryable() {
+                    break outcome;
+                }
+                info!("Attempt {} of {} failed: {}", attempts, uri, outcome);
+                outcome::backoff(attempts).await;
+            };
+            summary.record(uri, outcome, attempts);
+            if testcase == "zerortt" && i == 0 {
+                // The first connection brings the session ticket, the other files are requested
+                // in 0-RTT on a second one
+                if let Some(session) = session.take() {
+                    session.close(false, &mut report).await;
                 }
-                out.flush().await.expect("flush");
-                info!("File created");
             }
-            Ok::<_, Box<dyn std::error::Error>>(())
-        };
+        }
 
-        let (req_res, drive_res) = tokio::join!(request, drive);
-        req_res?;
-        drive_res?;
+        if let Some(session) = session {
+            session.close(false, &mut report).await;
+        }
         client_endpoint.wait_idle().await;
         info!("Finish request");
     } else {
-        let client_crypto = certs_configuration::get_client_crypto(&testcase)?;
-        for uri in config.requests {
+        let client_crypto = certs_configuration::get_client_crypto(&config)?;
+        let timeout = Duration::from_secs(config.request_timeout_s);
+        for uri in &config.requests {
             let dest = uri.parse::<http::Uri>()?;
-            if dest.scheme() != Some(&http::uri::Scheme::HTTPS) {
-                Err("destination scheme must be 'https'")?;
-            }
-            let auth = dest
-                .authority()
-                .ok_or("destination must have a host")?
-                .clone();
-            let port = auth.port_u16().unwrap_or(443);
-            let addr = match tokio::net::lookup_host((auth.host(), port)).await {
-                Ok(mut addr) => addr.next().unwrap(),
-                Err(_) => (auth.host(), port).to_socket_addrs()?.next().unwrap(),
-            };
-            info!("DNS Lookup for {:?}: {:?}", dest, addr);
-            let client_config = quinn::ClientConfig::new(Arc::new(client_crypto.clone()));
-            let mut client_endpoint = h3_quinn::quinn::Endpoint::client("[::]:0".parse().unwrap())?;
-            client_endpoint.set_default_client_config(client_config);
-            let quinn_conn =
-                h3_quinn::Connection::new(client_endpoint.connect(addr, "localhost")?.await?);
-            info!("QUIC connected ...");
-            // generic h3
-            let (mut driver, mut send_request) = h3::client::new(quinn_conn).await?;
-            let drive = async move {
-                future::poll_fn(|cx| driver.poll_close(cx)).await?;
-                Ok::<(), Box<dyn std::error::Error>>(())
-            };
-            let downloads = config.downloads.clone();
-            let requested_path = String::from(dest.path());
-            let request = async move {
-                info!("Sending request ...");
-                let req = http::Request::builder().uri(dest).body(())?;
-                let mut stream = send_request.send_request(req).await?;
-                stream.finish().await?;
-                info!("Receiving response ...");
-                let resp = stream.recv_response().await?;
-                info!("Response: {:?} {}", resp.version(), resp.status());
-                info!("Headers: {:#?}", resp.headers());
-                let downloads_path = Path::new(&downloads);
-                let requested_path = requested_path.split_at(1).1;
-                let requested_path = downloads_path.join(requested_path);
-                info!("Requested file path is: {:#?}", requested_path);
-                let mut out = tokio::fs::File::create(&requested_path).await?;
-                while let Some(chunk) = stream.recv_data().await? {
-                    out.write_all(&chunk).await.expect("write_all");
+            let requested_path = download_path(&config.downloads, &dest);
+            let mut attempts = 0;
+            let outcome = loop {
+                attempts += 1;
+                let mut timer = RequestTimer::start(uri);
+                // Every attempt opens its own connection, kept here so that it is reported even
+                // when the attempt fails
+                let mut connected = None;
+                let attempt = async {
+                    let (dest, addr) = resolve(uri).await?;
+                    let client_config = make_client_config(
+                        client_crypto.clone(),
+                        &config.quic_versions,
+                        &config.transport,
+                    )?;
+                    let mut client_endpoint =
+                        make_endpoint(&config.quic_versions, &config.transport)?;
+                    client_endpoint.set_default_client_config(client_config);
+                    let handshake_start = Instant::now();
+                    let connecting =
+                        client_endpoint.connect(addr, &server_name(&dest, config.insecure)?)?;
+                    let new_conn = connect(connecting, false).await?;
+                    connected = Some((
+                        handshake_start.elapsed(),
+                        new_conn.connection.clone(),
+                        client_endpoint,
+                    ));
+                    let quinn_conn = h3_quinn::Connection::new(new_conn);
+                    info!(
+                        "QUIC connected using the version of the first Initial, {}, congestion controller: {} ...",
+                        commons::version_name(first_version(&config.quic_versions)),
+                        config.transport.congestion_controller_name()
+                    );
+                    // generic h3
+                    let (mut driver, mut send_request) = h3::client::new(quinn_conn).await?;
+                    let drive = async move {
+                        future::poll_fn(|cx| driver.poll_close(cx)).await?;
+                        Ok::<(), Box<dyn std::error::Error>>(())
+                    };
+                    let request = async {
+                        info!("Sending request ...");
+                        let req = http::Request::builder().uri(dest).body(())?;
+                        let mut stream = send_request.send_request(req).await?;
+                        stream.finish().await?;
+                        info!("Receiving response ...");
+                        let resp = stream.recv_response().await?;
+                        timer.response(resp.status());
+                        info!("Response: {:?} {}", resp.version(), resp.status());
+                        info!("Headers: {:#?}", resp.headers());
+                        let mut integrity = Integrity::new(&resp)?;
+                        info!("Requested file path is: {:#?}", requested_path);
+                        let mut out = tokio::fs::File::create(&requested_path).await?;
+                        while let Some(chunk) = stream.recv_data().await? {
+                            integrity.update(&chunk)?;
+                            timer.data(chunk.len());
+                            out.write_all(&chunk).await?;
+                        }
+                        out.flush().await?;
+                        if let Some(trailers) = stream.recv_trailers().await? {
+                            integrity.trailers(&trailers)?;
+                        }
+                        integrity.finish()?;
+                        info!("File created");
+                        Ok::<(), Box<dyn Error>>(())
+                    };
+                    let (result, drive_res) = tokio::join!(request, drive);
+                    result?;
+                    drive_res
+                };
+                let outcome = Outcome::of(timeout, attempt).await;
+                if let Some((handshake, connection, client_endpoint)) = connected {
+                    report.connections.push(ConnectionReport::new(
+                        handshake,
+                        &connection.stats(),
+                        vec![timer.finish()],
+                    ));
+                    if !outcome.is_success() {
+                        // H3_NO_ERROR, the connection may still be open after a failure
+                        connection.close(quinn::VarInt::from_u32(0x100), b"");
+                    }
+                    client_endpoint.wait_idle().await;
+                    info!("Finish request");
                 }
-                out.flush().await.expect("flush");
-                info!("File created");
-                Ok::<_, Box<dyn std::error::Error>>(())
+                if !outcome.is_success() {
+                    remove_partial(&requested_path).await;
+                }
+                if attempts > config.retries || !outcome.is_retryable() {
+                    break outcome;
+                }
+                info!("Attempt {} of {} failed: {}", attempts, uri, outcome);
+                outcome::backoff(attempts).await;
             };
-            let (req_res, drive_res) = tokio::join!(request, drive);
-            req_res?;
-            drive_res?;
-            client_endpoint.wait_idle().await;
-            info!("Finish request");
+            summary.record(uri, outcome, attempts);
         }
     }
 
     info!("Finished  all requests");
 
+    report.duration_ms = report::millis(run_start.elapsed());
+    report.print_table();
+    summary.print();
+    if !logs.is_empty() {
+        info!("Report written to {}", report.write_json(&logs)?);
+    }
+    summary.into_result()?;
+
     Ok(())
 }
+
+/// The file a download is stored in: the path of the URI inside the downloads directory.
+fn download_path(downloads: &str, dest: &http::Uri) -> PathBuf {
+    Path::new(downloads).join(dest.path().trim_start_matches('/'))
+}
+
+/// It removes a download that failed, so that no partial or corrupt file is left behind.
+async fn remove_partial(path: &Path) {
+    match tokio::fs::remove_file(path).await {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+            info!("Unable to remove the partial download {:?}: {}", path, err);
+        }
+        _ => {}
+    }
+}
+
+/// A connection the downloads share, with the requests made on it for the report. `R` is the
+/// h3 request sender.
+struct Session<R> {
+    handshake: Duration,
+    connection: quinn::Connection,
+    send_request: R,
+    driver: tokio::task::JoinHandle<()>,
+    requests: Vec<RequestReport>,
+}
+
+impl<R> Session<R> {
+    /// It closes the connection and adds it to the report. After a failure the connection is
+    /// closed right away, otherwise h3 closes it once its requests are over.
+    async fn close(self, failed: bool, report: &mut Report) {
+        drop(self.send_request);
+        if failed {
+            // H3_NO_ERROR, the connection may still be open after a failure
+            self.connection.close(quinn::VarInt::from_u32(0x100), b"");
+        }
+        let _ = self.driver.await;
+        report.connections.push(ConnectionReport::new(
+            self.handshake,
+            &self.connection.stats(),
+            self.requests,
+        ));
+    }
+}
+
+/// It waits for the handshake and logs the negotiated TLS parameters. With `zero_rtt`, the
+/// connection is returned at once if a session ticket of the server is stored, so that the
+/// first packets are sent in 0-RTT, and the parameters are logged once the handshake is over.
+async fn connect(
+    connecting: quinn::Connecting,
+    zero_rtt: bool,
+) -> Result<quinn::NewConnection, Box<dyn Error>> {
+    let connecting = if zero_rtt {
+        match connecting.into_0rtt() {
+            Ok((new_conn, accepted)) => {
+                info!("Sending the requests in 0-RTT");
+                let connection = new_conn.connection.clone();
+                tokio::spawn(async move {
+                    if accepted.await {
+                        info!("0-RTT accepted");
+                    } else {
+                        // The streams opened in 0-RTT fail, their downloads end in a transport error
+                        info!("0-RTT rejected by the server");
+                    }
+                    info!("TLS {}", commons::handshake::describe(&connection));
+                });
+                return Ok(new_conn);
+            }
+            Err(connecting) => connecting,
+        }
+    } else {
+        connecting
+    };
+    let new_conn = connecting.await?;
+    info!("TLS {}", commons::handshake::describe(&new_conn.connection));
+    Ok(new_conn)
+}
+
+/// Parses a request URI and resolves the address of its host.
+async fn resolve(uri: &str) -> Result<(http::Uri, SocketAddr), Box<dyn Error>> {
+    let dest = uri.parse::<http::Uri>()?;
+    if dest.scheme() != Some(&http::uri::Scheme::HTTPS) {
+        Err("destination scheme must be 'https'")?;
+    }
+    let auth = dest
+        .authority()
+        .ok_or("destination must have a host")?
+        .clone();
+    let port = auth.port_u16().unwrap_or(443);
+    let addr = match tokio::net::lookup_host((auth.host(), port)).await {
+        Ok(mut addr) => addr.next().unwrap(),
+        Err(_) => (auth.host(), port).to_socket_addrs()?.next().unwrap(),
+    };
+    info!("DNS Lookup for {:?}: {:?}", dest, addr);
+    Ok((dest, addr))
+}
+
+/// The name sent as SNI and checked against the certificate: the host of the URI. The
+/// certificate of an IP address can't be verified, so IP hosts are only accepted in insecure
+/// mode, with "localhost" as name.
+fn server_name(dest: &http::Uri, insecure: bool) -> Result<String, Box<dyn Error>> {
+    let host = dest.host().ok_or("destination must have a host")?;
+    let ip = host.trim_start_matches('[').trim_end_matches(']');
+    if ip.parse::<IpAddr>().is_err() {
+        return Ok(host.to_string());
+    }
+    if !insecure {
+        Err(format!(
+            "cannot verify a certificate for the IP address {}, use a host name or --insecure",
+            host
+        ))?;
+    }
+    Ok(String::from("localhost"))
+}
+
+/// Builds the quinn client config, starting the handshake with the first configured version.
+fn make_client_config(
+    client_crypto: rustls::ClientConfig,
+    quic_versions: &[u32],
+    transport: &TransportParameters,
+) -> Result<quinn::ClientConfig, Box<dyn Error>> {
+    let mut client_config = quinn::ClientConfig::new(Arc::new(commons::handshake::ClientCrypto(
+        Arc::new(client_crypto),
+    )));
+    client_config.version(first_version(quic_versions));
+    let mut transport_config = quinn::TransportConfig::default();
+    transport.apply(&mut transport_config)?;
+    client_config.transport = Arc::new(transport_config);
+    Ok(client_config)
+}
+
+/// Binds the client endpoint on a random port, accepting only the configured versions.
+fn make_endpoint(
+    quic_versions: &[u32],
+    transport: &TransportParameters,
+) -> Result<quinn::Endpoint, Box<dyn Error>> {
+    let mut endpoint_config = quinn::EndpointConfig::default();
+    if !quic_versions.is_empty() {
+        endpoint_config.supported_versions(quic_versions.to_vec());
+    }
+    transport.apply_endpoint(&mut endpoint_config)?;
+    let socket = UdpSocket::bind("[::]:0")?;
+    let (endpoint, _) = quinn::Endpoint::new(endpoint_config, None, socket)?;
+    Ok(endpoint)
+}
+
+/// The version quinn uses for the client's first Initial packet. quinn 0.8 doesn't expose the
+/// version of a connection, but it is this one: a Version Negotiation packet fails the
+/// connection instead of starting it again with another version.
+fn first_version(quic_versions: &[u32]) -> u32 {
+    quic_versions.first().copied().unwrap_or(commons::QUIC_V1)
+}
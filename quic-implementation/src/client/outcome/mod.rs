@@ -0,0 +1,180 @@
+use std::error::Error;
+use std::fmt;
+use std::future::Future;
+use std::time::Duration;
+
+use tracing::info;
+
+// The exit codes of the client, next to the ones of `commons::validation`: 127 for an
+// unsupported testcase and 1 for an invalid configuration or an error that stops the run.
+
+/// The exit code used when every download succeeded.
+pub const SUCCESS_EXIT_CODE: i32 = 0;
+/// The exit code used when a download got a status other than 2xx and none failed worse.
+pub const HTTP_ERROR_EXIT_CODE: i32 = 3;
+/// The exit code used when a download failed because of the connection, the stream or a body
+/// that doesn't match its headers, and none timed out.
+pub const TRANSPORT_ERROR_EXIT_CODE: i32 = 4;
+/// The exit code used when a download timed out.
+pub const TIMEOUT_EXIT_CODE: i32 = 5;
+
+/// The wait before the first retry, doubled at every following one.
+const FIRST_RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// How a download ended, after its last attempt.
+#[derive(Debug)]
+pub enum Outcome {
+    Success,
+    /// The server answered with a status other than 2xx.
+    HttpError(http::StatusCode),
+    /// The connection or the stream failed, or the body doesn't match its headers.
+    TransportError(String),
+    /// The attempt took longer than the request timeout.
+    Timeout,
+}
+
+/// The error of a response whose status is not 2xx, so that it is not mistaken for a transport
+/// error.
+#[derive(Debug)]
+pub struct HttpStatus(pub http::StatusCode);
+
+/// The downloads of a run: how each one ended and after how many attempts.
+#[derive(Default)]
+pub struct Summary {
+    downloads: Vec<(String, Outcome, u32)>,
+}
+
+/// The error of a run where some downloads failed. The exit code is the one of the worst
+/// outcome: a timeout, then a transport error, then an HTTP error.
+#[derive(Debug)]
+pub struct DownloadsFailed {
+    failures: Vec<String>,
+    exit_code: i32,
+}
+
+impl Outcome {
+    /// It runs one attempt of a download, giving up after `timeout`.
+    pub async fn of(
+        timeout: Duration,
+        attempt: impl Future<Output = Result<(), Box<dyn Error>>>,
+    ) -> Outcome {
+        match tokio::time::timeout(timeout, attempt).await {
+            Ok(Ok(())) => Outcome::Success,
+            Ok(Err(e)) => match e.downcast_ref::<HttpStatus>() {
+                Some(HttpStatus(status)) => Outcome::HttpError(*status),
+                None => Outcome::TransportError(e.to_string()),
+            },
+            Err(_) => Outcome::Timeout,
+        }
+    }
+
+    pub fn is_success(&self) -> bool {
+        matches!(self, Outcome::Success)
+    }
+
+    /// A failure another attempt may fix: a transport error, a timeout, or a status telling the
+    /// client to come back later.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Outcome::Success => false,
+            Outcome::HttpError(status) => {
+                matches!(status.as_u16(), 408 | 425 | 429 | 502 | 503 | 504)
+            }
+            Outcome::TransportError(_) | Outcome::Timeout => true,
+        }
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Outcome::Success => SUCCESS_EXIT_CODE,
+            Outcome::HttpError(_) => HTTP_ERROR_EXIT_CODE,
+            Outcome::TransportError(_) => TRANSPORT_ERROR_EXIT_CODE,
+            Outcome::Timeout => TIMEOUT_EXIT_CODE,
+        }
+    }
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Outcome::Success => write!(f, "ok"),
+            Outcome::HttpError(status) => write!(f, "HTTP error: {}", status),
+            Outcome::TransportError(e) => write!(f, "transport error: {}", e),
+            Outcome::Timeout => write!(f, "timeout"),
+        }
+    }
+}
+
+impl fmt::Display for HttpStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "the server answered {}", self.0)
+    }
+}
+
+impl Error for HttpStatus {}
+
+impl Summary {
+    pub fn record(&mut self, uri: &str, outcome: Outcome, attempts: u32) {
+        info!(
+            "Download of {}: {} after {} attempts",
+            uri, outcome, attempts
+        );
+        self.downloads.push((uri.to_string(), outcome, attempts));
+    }
+
+    /// It prints a line per download.
+    pub fn print(&self) {
+        println!("{:<40} {:>8} outcome", "request", "attempts");
+        for (uri, outcome, attempts) in &self.downloads {
+            println!("{:<40} {:>8} {}", uri, attempts, outcome);
+        }
+    }
+
+    /// It fails if any download did.
+    pub fn into_result(self) -> Result<(), DownloadsFailed> {
+        let exit_code = self
+            .downloads
+            .iter()
+            .map(|(_, outcome, _)| outcome.exit_code())
+            .max()
+            .unwrap_or(SUCCESS_EXIT_CODE);
+        if exit_code == SUCCESS_EXIT_CODE {
+            return Ok(());
+        }
+        Err(DownloadsFailed {
+            failures: self
+                .downloads
+                .into_iter()
+                .filter(|(_, outcome, _)| !outcome.is_success())
+                .map(|(uri, outcome, _)| format!("{}: {}", uri, outcome))
+                .collect(),
+            exit_code,
+        })
+    }
+}
+
+impl DownloadsFailed {
+    /// The exit code of the process for this error.
+    pub fn exit_code(&self) -> i32 {
+        self.exit_code
+    }
+}
+
+impl fmt::Display for DownloadsFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} downloads failed:", self.failures.len())?;
+        for failure in &self.failures {
+            write!(f, "\n  {}", failure)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for DownloadsFailed {}
+
+/// It waits before the retry following `attempt`, counted from 1.
+pub async fn backoff(attempt: u32) {
+    let delay = FIRST_RETRY_DELAY * 2u32.saturating_pow(attempt.saturating_sub(1).min(16));
+    info!("Retrying in {} ms", delay.as_millis());
+    tokio::time::sleep(delay).await;
+}
@@ -0,0 +1,157 @@
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use quinn_proto::ConnectionStats;
+use serde::Serialize;
+
+/// The name of the JSON report written into the logs directory.
+const REPORT_FILE: &str = "client_report.json";
+
+/// What the client measured during a run: one entry per connection, each with its requests.
+#[derive(Serialize, Debug)]
+pub struct Report {
+    pub testcase: String,
+    pub duration_ms: f64,
+    pub connections: Vec<ConnectionReport>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ConnectionReport {
+    pub handshake_ms: f64,
+    pub rtt_ms: f64,
+    pub cwnd: u64,
+    /// The times quinn detected lost packets or an increase of the ECN CE count, and told the
+    /// congestion controller. It stands in for the lost packets, which quinn-proto 0.8 doesn't
+    /// expose: the packets found lost together make a single event.
+    pub congestion_events: u64,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub requests: Vec<RequestReport>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct RequestReport {
+    pub url: String,
+    pub status: u16,
+    pub bytes: u64,
+    /// Time until the response headers arrived.
+    pub ttfb_ms: f64,
+    pub duration_ms: f64,
+    pub throughput_mbit_s: f64,
+}
+
+/// It times a request from the moment it is sent.
+pub struct RequestTimer {
+    url: String,
+    start: Instant,
+    ttfb: Option<Duration>,
+    status: u16,
+    bytes: u64,
+}
+
+impl Report {
+    pub fn new(testcase: &str) -> Report {
+        Report {
+            testcase: testcase.to_string(),
+            duration_ms: 0.0,
+            connections: Vec::new(),
+        }
+    }
+
+    /// It prints a table with a line per request and one per connection.
+    pub fn print_table(&self) {
+        println!(
+            "{:<40} {:>6} {:>12} {:>10} {:>12} {:>10}",
+            "request", "status", "bytes", "ttfb ms", "duration ms", "Mbit/s"
+        );
+        for (i, connection) in self.connections.iter().enumerate() {
+            for request in &connection.requests {
+                println!(
+                    "{:<40} {:>6} {:>12} {:>10.1} {:>12.1} {:>10.2}",
+                    request.url,
+                    request.status,
+                    request.bytes,
+                    request.ttfb_ms,
+                    request.duration_ms,
+                    request.throughput_mbit_s
+                );
+            }
+            println!(
+                "connection {}: handshake {:.1} ms, rtt {:.1} ms, cwnd {}, {} congestion events, {} bytes sent, {} bytes received",
+                i,
+                connection.handshake_ms,
+                connection.rtt_ms,
+                connection.cwnd,
+                connection.congestion_events,
+                connection.bytes_sent,
+                connection.bytes_received
+            );
+        }
+        println!("total duration {:.1} ms", self.duration_ms);
+    }
+
+    /// It writes the report as JSON into the logs directory and returns its path.
+    pub fn write_json(&self, logs: &str) -> Result<String, Box<dyn Error>> {
+        let path = Path::new(logs).join(REPORT_FILE);
+        fs::write(&path, serde_json::to_string_pretty(self)?)?;
+        Ok(path.to_string_lossy().into_owned())
+    }
+}
+
+impl ConnectionReport {
+    pub fn new(
+        handshake: Duration,
+        stats: &ConnectionStats,
+        requests: Vec<RequestReport>,
+    ) -> ConnectionReport {
+        ConnectionReport {
+            handshake_ms: millis(handshake),
+            rtt_ms: millis(stats.path.rtt),
+            cwnd: stats.path.cwnd,
+            congestion_events: stats.path.congestion_events,
+            bytes_sent: stats.udp_tx.bytes,
+            bytes_received: stats.udp_rx.bytes,
+            requests,
+        }
+    }
+}
+
+impl RequestTimer {
+    pub fn start(url: &str) -> RequestTimer {
+        RequestTimer {
+            url: url.to_string(),
+            start: Instant::now(),
+            ttfb: None,
+            status: 0,
+            bytes: 0,
+        }
+    }
+
+    /// It records the arrival of the response headers.
+    pub fn response(&mut self, status: http::StatusCode) {
+        self.ttfb = Some(self.start.elapsed());
+        self.status = status.as_u16();
+    }
+
+    pub fn data(&mut self, bytes: usize) {
+        self.bytes += bytes as u64;
+    }
+
+    pub fn finish(self) -> RequestReport {
+        let duration = self.start.elapsed();
+        RequestReport {
+            url: self.url,
+            status: self.status,
+            bytes: self.bytes,
+            ttfb_ms: millis(self.ttfb.unwrap_or(duration)),
+            duration_ms: millis(duration),
+            throughput_mbit_s: self.bytes as f64 * 8.0 / duration.as_secs_f64().max(1e-9) / 1e6,
+        }
+    }
+}
+
+pub fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
@@ -0,0 +1,154 @@
+use std::error::Error;
+use std::time::SystemTime;
+
+use rustls::client::{ServerCertVerified, ServerCertVerifier};
+use rustls::{Certificate, RootCertStore, ServerName};
+
+use super::super::commons::certificates;
+
+/// A verifier accepting any certificate. It is only meant for the interop runner, whose
+/// certificates are generated on the fly.
+pub struct YesVerifier;
+
+impl ServerCertVerifier for YesVerifier {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+}
+
+/// The signature algorithms accepted in a chain leading to a pinned certificate, the ones rustls
+/// accepts.
+static SUPPORTED_SIG_ALGS: &[&webpki::SignatureAlgorithm] = &[
+    &webpki::ECDSA_P256_SHA256,
+    &webpki::ECDSA_P256_SHA384,
+    &webpki::ECDSA_P384_SHA256,
+    &webpki::ECDSA_P384_SHA384,
+    &webpki::ED25519,
+    &webpki::RSA_PSS_2048_8192_SHA256_LEGACY_KEY,
+    &webpki::RSA_PSS_2048_8192_SHA384_LEGACY_KEY,
+    &webpki::RSA_PSS_2048_8192_SHA512_LEGACY_KEY,
+    &webpki::RSA_PKCS1_2048_8192_SHA256,
+    &webpki::RSA_PKCS1_2048_8192_SHA384,
+    &webpki::RSA_PKCS1_2048_8192_SHA512,
+    &webpki::RSA_PKCS1_3072_8192_SHA384,
+];
+
+/// A verifier accepting a leaf valid for the server name and for the current time if its public
+/// key is pinned, or if a chain of the certificates sent leads from it to a certificate whose
+/// public key is pinned. That certificate is the only trust anchor, so self-signed certificates
+/// can be pinned.
+pub struct PinVerifier {
+    pins: Vec<Vec<u8>>,
+}
+
+impl PinVerifier {
+    /// It takes the base64 encoded SHA-256 hashes of the DER SubjectPublicKeyInfo, as printed by
+    /// `openssl x509 -pubkey -noout | openssl pkey -pubin -outform der | openssl dgst -sha256 -binary | base64`.
+    pub fn new(pins: &[String]) -> Result<PinVerifier, Box<dyn Error>> {
+        let pins = pins
+            .iter()
+            .map(|pin| decode_pin(pin))
+            .collect::<Result<_, _>>()?;
+        Ok(PinVerifier { pins })
+    }
+
+    fn is_pinned(&self, cert: &Certificate) -> bool {
+        certificates::subject_public_key_info(&cert.0).is_some_and(|spki| {
+            let hash = ring::digest::digest(&ring::digest::SHA256, spki);
+            self.pins.iter().any(|pin| pin[..] == *hash.as_ref())
+        })
+    }
+}
+
+impl ServerCertVerifier for PinVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let name = match server_name {
+            ServerName::DnsName(name) => name.as_ref(),
+            _ => return Err(rustls::Error::UnsupportedNameType),
+        };
+        let dns_name = webpki::DnsNameRef::try_from_ascii_str(name)
+            .map_err(|_| rustls::Error::UnsupportedNameType)?;
+        let leaf = webpki::EndEntityCert::try_from(&end_entity.0[..])
+            .map_err(|e| rustls::Error::InvalidCertificateData(format!("{:?}", e)))?;
+        leaf.verify_is_valid_for_dns_name(dns_name)
+            .map_err(|e| rustls::Error::InvalidCertificateData(format!("{:?}", e)))?;
+
+        if self.is_pinned(end_entity) {
+            let (not_before, not_after) =
+                certificates::validity(&end_entity.0).ok_or_else(|| {
+                    rustls::Error::InvalidCertificateData(String::from("unreadable validity"))
+                })?;
+            if now < not_before || now > not_after {
+                return Err(rustls::Error::InvalidCertificateData(String::from(
+                    "the certificate is expired or not valid yet",
+                )));
+            }
+            return Ok(ServerCertVerified::assertion());
+        }
+
+        // A pinned certificate of the chain is only trusted once it is known to have signed it
+        let time =
+            webpki::Time::try_from(now).map_err(|_| rustls::Error::FailedToGetCurrentTime)?;
+        let chain: Vec<&[u8]> = intermediates.iter().map(|cert| &cert.0[..]).collect();
+        let chained = intermediates
+            .iter()
+            .filter(|cert| self.is_pinned(cert))
+            .filter_map(|cert| webpki::TrustAnchor::try_from_cert_der(&cert.0).ok())
+            .any(|anchor| {
+                leaf.verify_is_valid_tls_server_cert(
+                    SUPPORTED_SIG_ALGS,
+                    &webpki::TlsServerTrustAnchors(&[anchor]),
+                    &chain,
+                    time,
+                )
+                .is_ok()
+            });
+        if !chained {
+            return Err(rustls::Error::General(String::from(
+                "no valid chain leads to a pinned public key",
+            )));
+        }
+        Ok(ServerCertVerified::assertion())
+    }
+}
+
+/// It decodes a pin, checking it is the size of a SHA-256 hash.
+pub fn decode_pin(pin: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+    let hash = base64::decode(pin).map_err(|e| format!("{} is not base64: {}", pin, e))?;
+    if hash.len() != 32 {
+        Err(format!("{} is not a SHA-256 hash", pin))?;
+    }
+    Ok(hash)
+}
+
+/// It reads the roots from a PEM bundle, or from the platform store if the path is empty.
+pub fn load_roots(ca_file: &str) -> Result<RootCertStore, Box<dyn Error>> {
+    if !ca_file.is_empty() {
+        return certificates::read_roots(ca_file);
+    }
+    let mut roots = RootCertStore::empty();
+    for cert in rustls_native_certs::load_native_certs()? {
+        // The platform stores often contain certificates webpki can't parse, they are skipped
+        let _ = roots.add(&Certificate(cert.0));
+    }
+    if roots.is_empty() {
+        Err("no root certificate found in the platform store")?;
+    }
+    Ok(roots)
+}
@@ -0,0 +1,190 @@
+use std::error::Error;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+use rand::RngCore;
+use tokio::net::UdpSocket;
+use tracing::info;
+
+use super::super::commons;
+
+/// A reserved version following the 0x?a?a?a?a pattern of RFC 9000, section 15. No server can
+/// support it, so it always triggers a Version Negotiation packet.
+pub const GREASED_VERSION: u32 = 0x1a2a_3a4a;
+
+/// The minimum size of a datagram carrying a client Initial packet. Servers drop smaller ones
+/// without answering.
+const MIN_INITIAL_SIZE: usize = 1200;
+
+const CID_LEN: usize = 8;
+const ATTEMPTS: u32 = 3;
+const ATTEMPT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// It sends a long header packet with a greased version to the server and returns the versions
+/// listed in the Version Negotiation packet the server answers with.
+///
+/// quinn refuses to start a handshake with a version it doesn't implement, so the packet is built
+/// by hand on a dedicated UDP socket. Reserved versions echoed back by the server are left out.
+pub async fn probe(addr: SocketAddr) -> Result<Vec<u32>, Box<dyn Error>> {
+    let bind_addr = if addr.is_ipv6() {
+        SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), 0)
+    } else {
+        SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0)
+    };
+    let socket = UdpSocket::bind(bind_addr).await?;
+
+    let mut dst_cid = [0u8; CID_LEN];
+    let mut src_cid = [0u8; CID_LEN];
+    rand::thread_rng().fill_bytes(&mut dst_cid);
+    rand::thread_rng().fill_bytes(&mut src_cid);
+
+    let mut packet = Vec::with_capacity(MIN_INITIAL_SIZE);
+    // Long header, fixed bit, Initial packet type
+    packet.push(0xc0);
+    packet.extend_from_slice(&GREASED_VERSION.to_be_bytes());
+    packet.push(CID_LEN as u8);
+    packet.extend_from_slice(&dst_cid);
+    packet.push(CID_LEN as u8);
+    packet.extend_from_slice(&src_cid);
+    packet.resize(MIN_INITIAL_SIZE, 0);
+
+    let mut buf = [0u8; 1500];
+    for attempt in 1..=ATTEMPTS {
+        info!(
+            "Sending version {} to {:?} (attempt {})",
+            commons::version_name(GREASED_VERSION),
+            addr,
+            attempt
+        );
+        socket.send_to(&packet, addr).await?;
+        let len = match tokio::time::timeout(ATTEMPT_TIMEOUT, socket.recv(&mut buf)).await {
+            Ok(len) => len?,
+            Err(_) => continue,
+        };
+        let versions = parse_version_negotiation(&buf[..len], &dst_cid, &src_cid)?;
+        info!(
+            "Server offered versions: {:?}",
+            versions
+                .iter()
+                .map(|&v| commons::version_name(v))
+                .collect::<Vec<_>>()
+        );
+        return Ok(versions);
+    }
+
+    Err("no Version Negotiation packet received from the server".into())
+}
+
+/// It picks the first of our versions that the server offered. If our list is empty, the quinn
+/// defaults are considered.
+pub fn choose(offered: &[u32], supported: &[u32]) -> Result<u32, Box<dyn Error>> {
+    let default_versions = [commons::QUIC_V1];
+    let supported = if supported.is_empty() {
+        &default_versions[..]
+    } else {
+        supported
+    };
+    match supported.iter().find(|v| offered.contains(v)) {
+        Some(&version) => Ok(version),
+        None => Err("the server doesn't support any of our versions".into()),
+    }
+}
+
+fn parse_version_negotiation(
+    packet: &[u8],
+    dst_cid: &[u8],
+    src_cid: &[u8],
+) -> Result<Vec<u32>, Box<dyn Error>> {
+    if packet.len() < 5 || packet[0] & 0x80 == 0 || packet[1..5] != [0, 0, 0, 0] {
+        Err("the server answered with something else than a Version Negotiation packet")?;
+    }
+    // The server swaps the connection IDs of our packet
+    let (echoed_dst_cid, rest) =
+        read_cid(&packet[5..]).ok_or("truncated Version Negotiation packet")?;
+    let (echoed_src_cid, rest) = read_cid(rest).ok_or("truncated Version Negotiation packet")?;
+    if echoed_dst_cid != src_cid || echoed_src_cid != dst_cid {
+        Err("Version Negotiation packet with unexpected connection IDs")?;
+    }
+    let versions = rest
+        .chunks_exact(4)
+        .map(|x| u32::from_be_bytes([x[0], x[1], x[2], x[3]]))
+        .filter(|&v| v & 0x0f0f_0f0f != 0x0a0a_0a0a)
+        .collect();
+    Ok(versions)
+}
+
+/// Splits a length prefixed connection ID from the beginning of `buf`.
+fn read_cid(buf: &[u8]) -> Option<(&[u8], &[u8])> {
+    let (&len, rest) = buf.split_first()?;
+    let len = len as usize;
+    if rest.len() < len {
+        return None;
+    }
+    Some(rest.split_at(len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DST_CID: [u8; CID_LEN] = [1; CID_LEN];
+    const SRC_CID: [u8; CID_LEN] = [2; CID_LEN];
+
+    /// A Version Negotiation packet answering ours, listing the versions.
+    fn version_negotiation(versions: &[u32]) -> Vec<u8> {
+        let mut packet = vec![0x80, 0, 0, 0, 0];
+        packet.push(CID_LEN as u8);
+        packet.extend_from_slice(&SRC_CID);
+        packet.push(CID_LEN as u8);
+        packet.extend_from_slice(&DST_CID);
+        for version in versions {
+            packet.extend_from_slice(&version.to_be_bytes());
+        }
+        packet
+    }
+
+    #[test]
+    fn versions_are_parsed_without_the_reserved_ones() {
+        let packet = version_negotiation(&[commons::QUIC_V1, 0x1a2a_3a4a, 0xff00_001d]);
+        let versions = parse_version_negotiation(&packet, &DST_CID, &SRC_CID).unwrap();
+        assert_eq!(versions, vec![commons::QUIC_V1, 0xff00_001d]);
+    }
+
+    #[test]
+    fn a_packet_with_a_version_is_rejected() {
+        let mut packet = version_negotiation(&[commons::QUIC_V1]);
+        packet[1..5].copy_from_slice(&commons::QUIC_V1.to_be_bytes());
+        assert!(parse_version_negotiation(&packet, &DST_CID, &SRC_CID).is_err());
+    }
+
+    #[test]
+    fn connection_ids_have_to_be_swapped() {
+        let packet = version_negotiation(&[commons::QUIC_V1]);
+        assert!(parse_version_negotiation(&packet, &SRC_CID, &DST_CID).is_err());
+    }
+
+    #[test]
+    fn a_truncated_packet_is_rejected() {
+        let packet = version_negotiation(&[]);
+        assert!(parse_version_negotiation(&packet[..10], &DST_CID, &SRC_CID).is_err());
+    }
+
+    #[test]
+    fn our_first_offered_version_is_chosen() {
+        let chosen = choose(
+            &[commons::QUIC_V1, 0xff00_001d],
+            &[0xff00_001d, commons::QUIC_V1],
+        );
+        assert_eq!(chosen.unwrap(), 0xff00_001d);
+    }
+
+    #[test]
+    fn v1_is_chosen_without_configured_versions() {
+        assert_eq!(choose(&[commons::QUIC_V1], &[]).unwrap(), commons::QUIC_V1);
+    }
+
+    #[test]
+    fn no_common_version_is_an_error() {
+        assert!(choose(&[0xff00_001d], &[commons::QUIC_V1]).is_err());
+    }
+}
@@ -0,0 +1,376 @@
+use std::error::Error;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::{fmt, fs};
+
+use pkcs8::der::Document;
+
+use rustls::{Certificate, PrivateKey, RootCertStore};
+
+/// The OID of the commonName attribute, 2.5.4.3, DER encoded.
+const COMMON_NAME_OID: &[u8] = &[0x55, 0x04, 0x03];
+
+/// It reads the certificates of a PEM file, in the order they appear, or of a DER file.
+pub fn read_certs(path: &str) -> Result<Vec<Certificate>, Box<dyn Error>> {
+    let data = fs::read(path).map_err(|e| format!("cannot read {}: {}", path, e))?;
+    let certs = parse_certs(&data)?;
+    if certs.is_empty() {
+        Err(format!("no certificate found in {}", path))?;
+    }
+    Ok(certs)
+}
+
+/// It reads the private key of a PEM or DER file. An encrypted key is decrypted with the
+/// passphrase of `passphrase_file`.
+pub fn read_private_key(path: &str, passphrase_file: &str) -> Result<PrivateKey, Box<dyn Error>> {
+    let data = fs::read(path).map_err(|e| format!("cannot read {}: {}", path, e))?;
+    let (key, _) = parse_private_key(&data, passphrase_file)?;
+    Ok(key)
+}
+
+/// It builds a root store from the certificates of a PEM file.
+pub fn read_roots(path: &str) -> Result<RootCertStore, Box<dyn Error>> {
+    let certs = read_certs(path)?;
+    let mut roots = RootCertStore::empty();
+    let ders: Vec<Vec<u8>> = certs.into_iter().map(|cert| cert.0).collect();
+    let (_, ignored) = roots.add_parsable_certificates(&ders);
+    if ignored > 0 {
+        println!("{} certificates of {} could not be parsed", ignored, path);
+    }
+    if roots.is_empty() {
+        Err(format!("no usable root certificate in {}", path))?;
+    }
+    Ok(roots)
+}
+
+/// It reads the certificates of PEM data, or the single certificate of DER data.
+pub fn parse_certs(data: &[u8]) -> Result<Vec<Certificate>, Box<dyn Error>> {
+    if !String::from_utf8_lossy(data)
+        .trim_start()
+        .starts_with("-----BEGIN")
+    {
+        return Ok(vec![Certificate(data.to_vec())]);
+    }
+    let certs = rustls_pemfile::certs(&mut &*data)?
+        .into_iter()
+        .map(Certificate)
+        .collect();
+    Ok(certs)
+}
+
+/// The encodings a private key is read from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyFormat {
+    Pkcs8,
+    EncryptedPkcs8,
+    Pkcs1,
+    Sec1,
+}
+
+impl KeyFormat {
+    const ALL: [KeyFormat; 4] = [
+        KeyFormat::Pkcs8,
+        KeyFormat::EncryptedPkcs8,
+        KeyFormat::Pkcs1,
+        KeyFormat::Sec1,
+    ];
+
+    /// The label of the PEM block holding a key in this format.
+    fn pem_label(self) -> &'static str {
+        match self {
+            KeyFormat::Pkcs8 => "PRIVATE KEY",
+            KeyFormat::EncryptedPkcs8 => "ENCRYPTED PRIVATE KEY",
+            KeyFormat::Pkcs1 => "RSA PRIVATE KEY",
+            KeyFormat::Sec1 => "EC PRIVATE KEY",
+        }
+    }
+}
+
+impl fmt::Display for KeyFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            KeyFormat::Pkcs8 => "PKCS#8",
+            KeyFormat::EncryptedPkcs8 => "encrypted PKCS#8",
+            KeyFormat::Pkcs1 => "PKCS#1 RSA",
+            KeyFormat::Sec1 => "SEC1 EC",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Why a private key can't be used.
+#[derive(Debug)]
+pub enum KeyError {
+    /// No usable key was found. It lists every format tried, with why it failed.
+    Unreadable(Vec<(KeyFormat, String)>),
+    /// A key was read in the given format, but it is not the key of the leaf certificate.
+    Mismatch(KeyFormat),
+}
+
+impl fmt::Display for KeyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyError::Unreadable(tried) => {
+                write!(f, "no usable private key, tried")?;
+                for (i, (format, reason)) in tried.iter().enumerate() {
+                    let separator = if i == 0 { ":" } else { ";" };
+                    write!(f, "{} {} ({})", separator, format, reason)?;
+                }
+                write!(f, "; so it was not checked against the leaf certificate")
+            }
+            KeyError::Mismatch(format) => write!(
+                f,
+                "the {} private key does not match the leaf certificate",
+                format
+            ),
+        }
+    }
+}
+
+impl Error for KeyError {}
+
+/// It reads a private key from PEM (PKCS#8, encrypted PKCS#8, PKCS#1 or SEC1 blocks) or DER
+/// data. The first usable key is returned with its format. Encrypted keys are decrypted with
+/// the first line of `passphrase_file`, which is only read for them.
+pub fn parse_private_key(
+    data: &[u8],
+    passphrase_file: &str,
+) -> Result<(PrivateKey, KeyFormat), KeyError> {
+    let mut tried = Vec::new();
+    let mut candidates = Vec::new();
+    let text = String::from_utf8_lossy(data);
+    if text.trim_start().starts_with("-----BEGIN") {
+        let blocks = pem_blocks(&text);
+        for format in KeyFormat::ALL {
+            let block = blocks.iter().find(|(label, _)| label == format.pem_label());
+            match block {
+                Some((_, Ok(der))) => candidates.push((format, der.clone())),
+                Some((_, Err(reason))) => tried.push((format, reason.clone())),
+                None => tried.push((format, format!("no {} PEM block", format.pem_label()))),
+            }
+        }
+    } else {
+        match der_key_format(data) {
+            Some(format) => candidates.push((format, data.to_vec())),
+            None => {
+                for format in KeyFormat::ALL {
+                    tried.push((format, String::from("not a DER key of this format")));
+                }
+            }
+        }
+    }
+
+    for (format, der) in candidates {
+        match decode_key(format, der, passphrase_file) {
+            Ok(key) => return Ok((key, format)),
+            Err(reason) => tried.push((format, reason)),
+        }
+    }
+    Err(KeyError::Unreadable(tried))
+}
+
+/// It turns the DER of a key into a key rustls can sign with.
+fn decode_key(
+    format: KeyFormat,
+    der: Vec<u8>,
+    passphrase_file: &str,
+) -> Result<PrivateKey, String> {
+    let der = match format {
+        KeyFormat::EncryptedPkcs8 => {
+            let passphrase = read_passphrase(passphrase_file)?;
+            let encrypted = pkcs8::EncryptedPrivateKeyInfo::try_from(&der[..])
+                .map_err(|e| format!("invalid: {}", e))?;
+            let decrypted = encrypted
+                .decrypt(passphrase)
+                .map_err(|e| format!("cannot decrypt: {}", e))?;
+            decrypted.as_der().to_vec()
+        }
+        _ => der,
+    };
+    let key = PrivateKey(der);
+    rustls::sign::any_supported_type(&key).map_err(|_| String::from("unsupported key type"))?;
+    Ok(key)
+}
+
+/// The passphrase of encrypted keys: the first line of `path`.
+fn read_passphrase(path: &str) -> Result<String, String> {
+    if path.is_empty() {
+        return Err(String::from(
+            "encrypted, set --key-passphrase-file or tls.key_passphrase_file",
+        ));
+    }
+    let content = fs::read_to_string(path)
+        .map_err(|e| format!("cannot read passphrase file {}: {}", path, e))?;
+    Ok(content.lines().next().unwrap_or_default().to_string())
+}
+
+/// It guesses the format of a DER key from its first fields.
+fn der_key_format(der: &[u8]) -> Option<KeyFormat> {
+    if der.first() != Some(&0x30) {
+        return None;
+    }
+    let (fields, _) = der_split(der)?;
+    match fields.first()? {
+        // EncryptedPrivateKeyInfo starts with the encryption AlgorithmIdentifier
+        0x30 => Some(KeyFormat::EncryptedPkcs8),
+        0x02 => {
+            let (version, rest) = der_split(fields)?;
+            match (version, rest.first()?) {
+                ([0], 0x30) => Some(KeyFormat::Pkcs8),
+                ([0], 0x02) => Some(KeyFormat::Pkcs1),
+                ([1], 0x04) => Some(KeyFormat::Sec1),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// It returns the label and decoded content of every block of a PEM text.
+fn pem_blocks(pem: &str) -> Vec<(String, Result<Vec<u8>, String>)> {
+    let mut blocks = Vec::new();
+    let mut current: Option<(String, String, bool)> = None;
+    for line in pem.lines().map(str::trim) {
+        if let Some(label) = line
+            .strip_prefix("-----BEGIN ")
+            .and_then(|rest| rest.strip_suffix("-----"))
+        {
+            current = Some((label.to_string(), String::new(), false));
+        } else if line.starts_with("-----END ") {
+            if let Some((label, base64, legacy_encrypted)) = current.take() {
+                let content = if legacy_encrypted {
+                    Err(String::from(
+                        "legacy OpenSSL encryption is not supported, convert it with `openssl pkcs8 -topk8`",
+                    ))
+                } else {
+                    base64::decode(&base64).map_err(|e| format!("invalid base64: {}", e))
+                };
+                blocks.push((label, content));
+            }
+        } else if let Some((_, base64, legacy_encrypted)) = &mut current {
+            if line.starts_with("Proc-Type:") && line.contains("ENCRYPTED") {
+                *legacy_encrypted = true;
+            } else if !line.contains(':') {
+                base64.push_str(line);
+            }
+        }
+    }
+    blocks
+}
+
+/// It returns the SubjectPublicKeyInfo of a DER certificate, with its tag and length.
+pub fn subject_public_key_info(cert: &[u8]) -> Option<&[u8]> {
+    // serialNumber, signature, issuer, validity and subject come before the key
+    let mut fields = tbs_certificate_fields(cert)?;
+    for _ in 0..5 {
+        fields = der_split(fields)?.1;
+    }
+    let (_, rest) = der_split(fields)?;
+    Some(&fields[..fields.len() - rest.len()])
+}
+
+/// It returns the first commonName of the subject of a DER certificate.
+pub fn common_name(cert: &[u8]) -> Option<String> {
+    // serialNumber, signature, issuer and validity come before the subject
+    let mut fields = tbs_certificate_fields(cert)?;
+    for _ in 0..4 {
+        fields = der_split(fields)?.1;
+    }
+    let (mut relative_names, _) = der_split(fields)?;
+    while !relative_names.is_empty() {
+        let (relative_name, rest) = der_split(relative_names)?;
+        relative_names = rest;
+        let (attribute, _) = der_split(relative_name)?;
+        let (oid, value) = der_split(attribute)?;
+        if oid == COMMON_NAME_OID {
+            let (value, _) = der_split(value)?;
+            return Some(String::from_utf8_lossy(value).into_owned());
+        }
+    }
+    None
+}
+
+/// It returns the notBefore and notAfter of a DER certificate.
+pub fn validity(cert: &[u8]) -> Option<(SystemTime, SystemTime)> {
+    // serialNumber, signature and issuer come before the validity
+    let mut fields = tbs_certificate_fields(cert)?;
+    for _ in 0..3 {
+        fields = der_split(fields)?.1;
+    }
+    let (validity, _) = der_split(fields)?;
+    let (_, not_after) = der_split(validity)?;
+    Some((der_time(validity)?, der_time(not_after)?))
+}
+
+/// It decodes the UTCTime or GeneralizedTime at the start of `input`, in the YYMMDDHHMMSSZ and
+/// YYYYMMDDHHMMSSZ forms RFC 5280 requires.
+fn der_time(input: &[u8]) -> Option<SystemTime> {
+    let tag = *input.first()?;
+    let (value, _) = der_split(input)?;
+    let value = std::str::from_utf8(value).ok()?.strip_suffix('Z')?;
+    let (year, rest) = match tag {
+        0x17 => {
+            let year: i64 = value.get(..2)?.parse().ok()?;
+            // UTCTime years from 50 are in the 1900s
+            (
+                if year >= 50 { 1900 + year } else { 2000 + year },
+                value.get(2..)?,
+            )
+        }
+        0x18 => (value.get(..4)?.parse().ok()?, value.get(4..)?),
+        _ => return None,
+    };
+    if rest.len() != 10 || !rest.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+    let field = |i: usize| -> Option<i64> { rest.get(i..i + 2)?.parse().ok() };
+    let seconds = days_from_civil(year, field(0)?, field(2)?) * 86400
+        + field(4)? * 3600
+        + field(6)? * 60
+        + field(8)?;
+    if seconds >= 0 {
+        UNIX_EPOCH.checked_add(Duration::from_secs(seconds as u64))
+    } else {
+        UNIX_EPOCH.checked_sub(Duration::from_secs(seconds.unsigned_abs()))
+    }
+}
+
+/// The days from 1970-01-01 to a date, with the days from civil algorithm of Howard Hinnant.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/// It returns the fields of the TBSCertificate of a DER certificate, after the optional version.
+fn tbs_certificate_fields(cert: &[u8]) -> Option<&[u8]> {
+    let (certificate, _) = der_split(cert)?;
+    let (mut fields, _) = der_split(certificate)?;
+    // The version is an optional explicit [0] field
+    if fields.first() == Some(&0xa0) {
+        fields = der_split(fields)?.1;
+    }
+    Some(fields)
+}
+
+/// It returns the content of the DER element at the start of `input` and what follows it.
+fn der_split(input: &[u8]) -> Option<(&[u8], &[u8])> {
+    let first_length_byte = *input.get(1)?;
+    let (header, length) = if first_length_byte < 0x80 {
+        (2, first_length_byte as usize)
+    } else {
+        let length_bytes = (first_length_byte & 0x7f) as usize;
+        if length_bytes == 0 || length_bytes > 4 {
+            return None;
+        }
+        let length = input
+            .get(2..2 + length_bytes)?
+            .iter()
+            .fold(0, |length, &byte| length << 8 | byte as usize);
+        (2 + length_bytes, length)
+    };
+    let content = input.get(header..header + length)?;
+    Some((content, &input[header + length..]))
+}
@@ -0,0 +1,41 @@
+use serde::Deserialize;
+
+/// The [quic] section of the configuration files of both binaries.
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct QuicSection {
+    pub versions: Option<String>,
+}
+
+/// It returns the value of a setting without default, given by the flag, the environment
+/// variable or the configuration file. If it is missing, a problem naming the key, the flag and
+/// the variable is added to `problems` and an empty value is returned.
+pub fn required(
+    value: Option<String>,
+    key: &str,
+    flag: &str,
+    problems: &mut Vec<String>,
+) -> String {
+    value.unwrap_or_else(|| {
+        problems.push(format!(
+            "`{}`: missing, set --{}, {} or the configuration file",
+            key,
+            flag,
+            flag.to_uppercase()
+        ));
+        String::new()
+    })
+}
+
+/// It returns the comma separated list of the command line or environment, else the list of the
+/// configuration file.
+pub fn split_list(value: Option<String>, from_file: Option<Vec<String>>) -> Vec<String> {
+    match value {
+        Some(value) => value
+            .split(',')
+            .map(|item| item.trim().to_string())
+            .filter(|item| !item.is_empty())
+            .collect(),
+        None => from_file.unwrap_or_default(),
+    }
+}
@@ -0,0 +1,384 @@
+use std::any::Any;
+use std::fmt;
+use std::sync::Arc;
+
+use bytes::BytesMut;
+use quinn::Connection;
+use quinn_proto::crypto::{
+    self, ExportKeyingMaterialError, HeaderKey, KeyPair, Keys, PacketKey, UnsupportedVersion,
+};
+use quinn_proto::transport_parameters::TransportParameters;
+use quinn_proto::{ConnectError, ConnectionId, Side, TransportError};
+use rustls::CipherSuite;
+
+use super::tls_config;
+
+/// The type of the ServerHello handshake message.
+const SERVER_HELLO: u8 = 2;
+/// The type of the key_share extension.
+const KEY_SHARE: u16 = 51;
+/// The random of a ServerHello that is a HelloRetryRequest (RFC 8446, section 4.1.3).
+const HELLO_RETRY_RANDOM: [u8; 32] = [
+    0xcf, 0x21, 0xad, 0x74, 0xe5, 0x9a, 0x61, 0x11, 0xbe, 0x1d, 0x8c, 0x02, 0x1e, 0x65, 0xb8, 0x91,
+    0xc2, 0xa2, 0x11, 0x16, 0x7a, 0xbb, 0x8c, 0x5e, 0x07, 0x9e, 0x09, 0xe2, 0xc8, 0xa8, 0x33, 0x9c,
+];
+
+/// The handshake data of the connections using [`ServerCrypto`] or [`ClientCrypto`]: the one of
+/// quinn's rustls session, with the cipher suite and key exchange group chosen by the server.
+pub struct HandshakeData {
+    /// The negotiated ALPN protocol.
+    pub protocol: Option<Vec<u8>>,
+    /// The SNI sent by the client, only known by the server.
+    pub server_name: Option<String>,
+    /// Known once the ServerHello has been sent or received.
+    pub negotiated: Option<Negotiated>,
+}
+
+/// The TLS parameters the server picked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Negotiated {
+    pub cipher_suite: CipherSuite,
+    /// The IANA code of the key exchange group.
+    pub kx_group: u16,
+}
+
+impl fmt::Display for Negotiated {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let group = rustls::ALL_KX_GROUPS
+            .iter()
+            .find(|group| group.name.get_u16() == self.kx_group)
+            .map_or(format!("{:#06x}", self.kx_group), |group| {
+                tls_config::group_name(group)
+            });
+        write!(
+            f,
+            "cipher suite {}, key exchange group {}",
+            tls_config::suite_name(self.cipher_suite),
+            group
+        )
+    }
+}
+
+/// The TLS parameters of a connection whose ServerHello went through.
+pub fn negotiated(connection: &Connection) -> Option<Negotiated> {
+    connection
+        .handshake_data()?
+        .downcast::<HandshakeData>()
+        .ok()?
+        .negotiated
+}
+
+/// The negotiated TLS parameters of a connection, for the logs.
+pub fn describe(connection: &Connection) -> String {
+    match negotiated(connection) {
+        Some(negotiated) => negotiated.to_string(),
+        None => String::from("unknown TLS parameters"),
+    }
+}
+
+/// A rustls server config whose sessions record the negotiated TLS parameters. quinn 0.8 doesn't
+/// give access to the rustls connection and rustls 0.20 doesn't tell the key exchange group, so
+/// they are read from the ServerHello the session writes.
+pub struct ServerCrypto(pub Arc<rustls::ServerConfig>);
+
+/// A rustls client config whose sessions record the negotiated TLS parameters, read from the
+/// ServerHello the session receives.
+pub struct ClientCrypto(pub Arc<rustls::ClientConfig>);
+
+impl crypto::ServerConfig for ServerCrypto {
+    fn initial_keys(
+        &self,
+        version: u32,
+        dst_cid: &ConnectionId,
+        side: Side,
+    ) -> Result<Keys, UnsupportedVersion> {
+        crypto::ServerConfig::initial_keys(&*self.0, version, dst_cid, side)
+    }
+
+    fn retry_tag(&self, version: u32, orig_dst_cid: &ConnectionId, packet: &[u8]) -> [u8; 16] {
+        crypto::ServerConfig::retry_tag(&*self.0, version, orig_dst_cid, packet)
+    }
+
+    fn start_session(
+        self: Arc<Self>,
+        version: u32,
+        params: &TransportParameters,
+    ) -> Box<dyn crypto::Session> {
+        let inner = crypto::ServerConfig::start_session(self.0.clone(), version, params);
+        Box::new(Session::new(inner, Side::Server))
+    }
+}
+
+impl crypto::ClientConfig for ClientCrypto {
+    fn start_session(
+        self: Arc<Self>,
+        version: u32,
+        server_name: &str,
+        params: &TransportParameters,
+    ) -> Result<Box<dyn crypto::Session>, ConnectError> {
+        let inner =
+            crypto::ClientConfig::start_session(self.0.clone(), version, server_name, params)?;
+        Ok(Box::new(Session::new(inner, Side::Client)))
+    }
+}
+
+/// A session passing everything to the rustls one, and the handshake messages of the server
+/// to a [`ServerHelloReader`].
+struct Session {
+    inner: Box<dyn crypto::Session>,
+    side: Side,
+    server_hello: ServerHelloReader,
+}
+
+impl Session {
+    fn new(inner: Box<dyn crypto::Session>, side: Side) -> Session {
+        Session {
+            inner,
+            side,
+            server_hello: ServerHelloReader::default(),
+        }
+    }
+}
+
+impl crypto::Session for Session {
+    fn initial_keys(&self, dst_cid: &ConnectionId, side: Side) -> Keys {
+        self.inner.initial_keys(dst_cid, side)
+    }
+
+    fn handshake_data(&self) -> Option<Box<dyn Any>> {
+        let data = self.inner.handshake_data()?;
+        let data = match data.downcast::<quinn_proto::crypto::rustls::HandshakeData>() {
+            Ok(data) => data,
+            Err(data) => return Some(data),
+        };
+        Some(Box::new(HandshakeData {
+            protocol: data.protocol,
+            server_name: data.server_name,
+            negotiated: self.server_hello.negotiated,
+        }))
+    }
+
+    fn peer_identity(&self) -> Option<Box<dyn Any>> {
+        self.inner.peer_identity()
+    }
+
+    fn early_crypto(&self) -> Option<(Box<dyn HeaderKey>, Box<dyn PacketKey>)> {
+        self.inner.early_crypto()
+    }
+
+    fn early_data_accepted(&self) -> Option<bool> {
+        self.inner.early_data_accepted()
+    }
+
+    fn is_handshaking(&self) -> bool {
+        self.inner.is_handshaking()
+    }
+
+    fn read_handshake(&mut self, buf: &[u8]) -> Result<bool, TransportError> {
+        // Only the client reads the messages of the server
+        if self.side == Side::Client {
+            self.server_hello.read(buf);
+        }
+        self.inner.read_handshake(buf)
+    }
+
+    fn transport_parameters(&self) -> Result<Option<TransportParameters>, TransportError> {
+        self.inner.transport_parameters()
+    }
+
+    fn write_handshake(&mut self, buf: &mut Vec<u8>) -> Option<Keys> {
+        let start = buf.len();
+        let keys = self.inner.write_handshake(buf);
+        if self.side == Side::Server {
+            self.server_hello.read(&buf[start..]);
+        }
+        keys
+    }
+
+    fn next_1rtt_keys(&mut self) -> Option<KeyPair<Box<dyn PacketKey>>> {
+        self.inner.next_1rtt_keys()
+    }
+
+    fn is_valid_retry(&self, orig_dst_cid: &ConnectionId, header: &[u8], payload: &[u8]) -> bool {
+        self.inner.is_valid_retry(orig_dst_cid, header, payload)
+    }
+
+    fn export_keying_material(
+        &self,
+        output: &mut [u8],
+        label: &[u8],
+        context: &[u8],
+    ) -> Result<(), ExportKeyingMaterialError> {
+        self.inner.export_keying_material(output, label, context)
+    }
+}
+
+/// It finds the ServerHello among the handshake messages of the server, which can be split
+/// across several reads, and stops looking once it is found.
+#[derive(Default)]
+struct ServerHelloReader {
+    pending: BytesMut,
+    negotiated: Option<Negotiated>,
+    done: bool,
+}
+
+impl ServerHelloReader {
+    fn read(&mut self, data: &[u8]) {
+        if self.done {
+            return;
+        }
+        self.pending.extend_from_slice(data);
+        // Each message is its type, its length on 3 bytes and its body
+        while self.pending.len() >= 4 {
+            let len = u32::from_be_bytes([0, self.pending[1], self.pending[2], self.pending[3]]);
+            if self.pending.len() < 4 + len as usize {
+                return;
+            }
+            let message = self.pending.split_to(4 + len as usize);
+            if message[0] != SERVER_HELLO {
+                continue;
+            }
+            match parse_server_hello(&message[4..]) {
+                // The server sends a new ServerHello after a HelloRetryRequest
+                Some((negotiated, true)) => self.negotiated = Some(negotiated),
+                Some((negotiated, false)) => {
+                    self.negotiated = Some(negotiated);
+                    self.stop();
+                }
+                None => self.stop(),
+            }
+        }
+    }
+
+    fn stop(&mut self) {
+        self.done = true;
+        self.pending = BytesMut::new();
+    }
+}
+
+/// The cipher suite and key exchange group of a ServerHello body, and whether it is a
+/// HelloRetryRequest.
+fn parse_server_hello(body: &[u8]) -> Option<(Negotiated, bool)> {
+    let (_legacy_version, body) = split(body, 2)?;
+    let (random, body) = split(body, 32)?;
+    let (session_id_len, body) = split(body, 1)?;
+    let (_session_id, body) = split(body, session_id_len[0] as usize)?;
+    let (cipher_suite, body) = split(body, 2)?;
+    let (_compression, body) = split(body, 1)?;
+    let (extensions_len, body) = split(body, 2)?;
+    let (mut extensions, _) = split(body, u16_at(extensions_len) as usize)?;
+    while !extensions.is_empty() {
+        let (header, rest) = split(extensions, 4)?;
+        let (data, rest) = split(rest, u16_at(&header[2..]) as usize)?;
+        if u16_at(header) == KEY_SHARE {
+            // The server's key share, or the group it asks for in a HelloRetryRequest
+            let (group, _) = split(data, 2)?;
+            let negotiated = Negotiated {
+                cipher_suite: CipherSuite::from(u16_at(cipher_suite)),
+                kx_group: u16_at(group),
+            };
+            return Some((negotiated, random == HELLO_RETRY_RANDOM));
+        }
+        extensions = rest;
+    }
+    None
+}
+
+fn split(buf: &[u8], n: usize) -> Option<(&[u8], &[u8])> {
+    if buf.len() < n {
+        return None;
+    }
+    Some(buf.split_at(n))
+}
+
+fn u16_at(buf: &[u8]) -> u16 {
+    u16::from_be_bytes([buf[0], buf[1]])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A ServerHello message with a key share of `group`.
+    fn server_hello(random: [u8; 32], cipher_suite: u16, group: u16) -> Vec<u8> {
+        let mut extensions = vec![0, 43, 0, 2, 3, 4];
+        extensions.extend_from_slice(&KEY_SHARE.to_be_bytes());
+        extensions.extend_from_slice(&[0, 6]);
+        extensions.extend_from_slice(&group.to_be_bytes());
+        extensions.extend_from_slice(&[0, 2, 0xaa, 0xbb]);
+
+        let mut body = vec![3, 3];
+        body.extend_from_slice(&random);
+        body.extend_from_slice(&[2, 0x11, 0x22]);
+        body.extend_from_slice(&cipher_suite.to_be_bytes());
+        body.push(0);
+        body.extend_from_slice(&(extensions.len() as u16).to_be_bytes());
+        body.extend_from_slice(&extensions);
+
+        let mut message = vec![SERVER_HELLO, 0];
+        message.extend_from_slice(&(body.len() as u16).to_be_bytes());
+        message.extend_from_slice(&body);
+        message
+    }
+
+    fn negotiated(cipher_suite: u16, kx_group: u16) -> Option<Negotiated> {
+        Some(Negotiated {
+            cipher_suite: CipherSuite::from(cipher_suite),
+            kx_group,
+        })
+    }
+
+    #[test]
+    fn the_server_hello_can_be_split_across_reads() {
+        let message = server_hello([1; 32], 0x1301, 0x001d);
+        let mut reader = ServerHelloReader::default();
+        for chunk in message.chunks(7) {
+            reader.read(chunk);
+        }
+        assert_eq!(reader.negotiated, negotiated(0x1301, 0x001d));
+        assert!(reader.done);
+    }
+
+    #[test]
+    fn the_messages_before_the_server_hello_are_skipped() {
+        let mut data = vec![8, 0, 0, 2, 0, 0];
+        data.extend_from_slice(&server_hello([1; 32], 0x1303, 0x0017));
+        let mut reader = ServerHelloReader::default();
+        reader.read(&data);
+        assert_eq!(reader.negotiated, negotiated(0x1303, 0x0017));
+    }
+
+    #[test]
+    fn the_server_hello_after_a_retry_request_wins() {
+        let mut reader = ServerHelloReader::default();
+        reader.read(&server_hello(HELLO_RETRY_RANDOM, 0x1301, 0x0017));
+        assert_eq!(reader.negotiated, negotiated(0x1301, 0x0017));
+        assert!(!reader.done);
+        reader.read(&server_hello([1; 32], 0x1301, 0x0018));
+        assert_eq!(reader.negotiated, negotiated(0x1301, 0x0018));
+        assert!(reader.done);
+    }
+
+    #[test]
+    fn a_truncated_server_hello_is_ignored() {
+        let mut message = server_hello([1; 32], 0x1301, 0x001d);
+        let len = message.len();
+        // The extensions claim more bytes than the message has
+        message[len - 17] = 0xff;
+        let mut reader = ServerHelloReader::default();
+        reader.read(&message);
+        assert_eq!(reader.negotiated, None);
+        assert!(reader.done);
+    }
+
+    #[test]
+    fn the_groups_are_named_by_rustls() {
+        let tls = negotiated(0x1301, 0x001d).unwrap();
+        assert_eq!(
+            tls.to_string(),
+            "cipher suite TLS13_AES_128_GCM_SHA256, key exchange group X25519"
+        );
+        let unknown = negotiated(0x1301, 0x0100).unwrap();
+        assert!(unknown.to_string().ends_with("group 0x0100"));
+    }
+}
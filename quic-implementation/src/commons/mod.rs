@@ -1,5 +1,14 @@
 use quinn::{ClientConfig, Endpoint, Incoming, ServerConfig};
-use std::{error::Error, net::SocketAddr, sync::Arc};
+use serde::de::DeserializeOwned;
+use std::{error::Error, fs, net::SocketAddr, sync::Arc};
+
+pub mod certificates;
+pub mod config;
+pub mod handshake;
+pub mod payload;
+pub mod tls_config;
+pub mod transport_config;
+pub mod validation;
 
 /// Constructs a QUIC endpoint configured for use a client only.
 ///
@@ -64,3 +73,76 @@ fn configure_server() -> Result<(ServerConfig, Vec<u8>), Box<dyn Error>> {
 
 #[allow(unused)]
 pub const ALPN: &[u8] = b"h3";
+
+/// The testcases in which the client changes its UDP socket in the middle of a transfer, and
+/// the server has to accept the new path.
+pub const MIGRATION_TESTCASES: [&str; 3] = ["rebind-port", "rebind-addr", "connectionmigration"];
+
+/// QUIC version 1 (RFC 9000).
+pub const QUIC_V1: u32 = 0x0000_0001;
+
+/// QUIC version 2 (RFC 9369). quinn 0.8 and rustls 0.20 only know the v1 and draft initial
+/// salts and key derivation labels, so this version can't be negotiated by this implementation.
+pub const QUIC_V2: u32 = 0x6b33_43cf;
+
+/// Parses a comma separated list of QUIC versions, written either in hexadecimal with the `0x`
+/// prefix or in decimal (e.g. `0x00000001,0xff00001d`).
+pub fn parse_versions(versions: &str) -> Result<Vec<u32>, Box<dyn Error>> {
+    let mut parsed = Vec::new();
+    for version in versions.split(',').map(str::trim).filter(|v| !v.is_empty()) {
+        let value = match version.strip_prefix("0x") {
+            Some(hex) => u32::from_str_radix(hex, 16)?,
+            None => version.parse()?,
+        };
+        if value == QUIC_V2 {
+            Err("QUIC version 2 is not supported, quinn 0.8 and rustls 0.20 lack its keys")?;
+        }
+        parsed.push(value);
+    }
+    Ok(parsed)
+}
+
+/// Returns a readable name for a QUIC version, used when logging the version of a connection.
+pub fn version_name(version: u32) -> String {
+    match version {
+        QUIC_V1 => String::from("v1"),
+        QUIC_V2 => String::from("v2"),
+        0xff00_001d..=0xff00_0022 => format!("draft-{}", version & 0xff),
+        _ => format!("{:#010x}", version),
+    }
+}
+
+/// Reads a TOML file into `T`. The errors name the file and, for invalid content, the offending
+/// key and line.
+pub fn read_toml_file<T: DeserializeOwned>(path: &str) -> Result<T, Box<dyn Error>> {
+    let content = fs::read_to_string(path).map_err(|e| format!("cannot read {}: {}", path, e))?;
+    let parsed = toml::from_str(&content).map_err(|e| format!("invalid {}: {}", path, e))?;
+    Ok(parsed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn versions_are_parsed_in_hexadecimal_and_decimal() {
+        let versions = parse_versions(" 0x00000001, 4278190109 ,").unwrap();
+        assert_eq!(versions, vec![QUIC_V1, 0xff00_001d]);
+    }
+
+    #[test]
+    fn an_empty_list_has_no_versions() {
+        assert!(parse_versions("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn invalid_versions_are_rejected() {
+        assert!(parse_versions("0xzz").is_err());
+        assert!(parse_versions("v1").is_err());
+    }
+
+    #[test]
+    fn version_2_is_rejected() {
+        assert!(parse_versions("0x00000001,0x6b3343cf").is_err());
+    }
+}
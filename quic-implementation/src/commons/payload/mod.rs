@@ -0,0 +1,88 @@
+/// The size of the chunks payloads are generated and sent in. It is a multiple of 8, so that
+/// generating chunk by chunk gives the same bytes as generating at once.
+pub const CHUNK_LEN: usize = 64 * 1024;
+
+/// The deterministic pseudo-random bytes served under /__gen/bytes: the little-endian outputs
+/// of a SplitMix64 generator started at the seed. Anyone knowing the seed and the length can
+/// generate the same payload again to check it.
+pub struct Payload {
+    state: u64,
+}
+
+impl Payload {
+    pub fn new(seed: u64) -> Payload {
+        Payload { state: seed }
+    }
+
+    /// It fills the buffer with the next bytes. Only the last buffer of a payload may have a
+    /// length that is not a multiple of 8.
+    pub fn fill(&mut self, buf: &mut [u8]) {
+        for chunk in buf.chunks_mut(8) {
+            let value = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&value[..chunk.len()]);
+        }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+}
+
+/// The SHA-256 of the payload of `len` bytes generated from `seed`.
+pub fn sha256(len: u64, seed: u64) -> ring::digest::Digest {
+    let mut payload = Payload::new(seed);
+    let mut context = ring::digest::Context::new(&ring::digest::SHA256);
+    let mut buf = vec![0u8; CHUNK_LEN];
+    let mut left = len;
+    while left > 0 {
+        let n = left.min(CHUNK_LEN as u64) as usize;
+        payload.fill(&mut buf[..n]);
+        context.update(&buf[..n]);
+        left -= n as u64;
+    }
+    context.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The whole payload of `len` bytes, generated at once.
+    fn generate(len: usize, seed: u64) -> Vec<u8> {
+        let mut buf = vec![0u8; len];
+        Payload::new(seed).fill(&mut buf);
+        buf
+    }
+
+    #[test]
+    fn the_first_bytes_are_those_of_splitmix64() {
+        assert_eq!(generate(8, 0), 0xe220_a839_7b1d_cdafu64.to_le_bytes());
+    }
+
+    #[test]
+    fn chunks_continue_the_same_payload() {
+        let mut payload = Payload::new(42);
+        let mut chunked = vec![0u8; 2 * CHUNK_LEN + 3];
+        for chunk in chunked.chunks_mut(CHUNK_LEN) {
+            payload.fill(chunk);
+        }
+        assert_eq!(chunked, generate(2 * CHUNK_LEN + 3, 42));
+    }
+
+    #[test]
+    fn seeds_give_different_payloads() {
+        assert_ne!(generate(64, 1), generate(64, 2));
+    }
+
+    #[test]
+    fn sha256_is_the_digest_of_the_payload() {
+        for len in [0, 5, CHUNK_LEN, CHUNK_LEN + 13] {
+            let expected = ring::digest::digest(&ring::digest::SHA256, &generate(len, 7));
+            assert_eq!(sha256(len as u64, 7).as_ref(), expected.as_ref());
+        }
+    }
+}
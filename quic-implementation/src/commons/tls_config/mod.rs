@@ -0,0 +1,85 @@
+use std::error::Error;
+
+use rustls::cipher_suite::TLS13_CHACHA20_POLY1305_SHA256;
+use rustls::{CipherSuite, SupportedCipherSuite, SupportedKxGroup};
+
+/// It returns the TLS 1.3 cipher suites with the given names, in the given order. Names are
+/// case-insensitive and may use the IANA (TLS_AES_128_GCM_SHA256) or the rustls
+/// (TLS13_AES_128_GCM_SHA256) spelling, with or without the prefix. Without names, the chacha20
+/// testcase only offers ChaCha20 and the others use the rustls defaults.
+pub fn cipher_suites(
+    names: &[String],
+    testcase: &str,
+) -> Result<Vec<SupportedCipherSuite>, Box<dyn Error>> {
+    if names.is_empty() {
+        if testcase == "chacha20" {
+            return Ok(vec![TLS13_CHACHA20_POLY1305_SHA256]);
+        }
+        return Ok(rustls::DEFAULT_CIPHER_SUITES
+            .iter()
+            .copied()
+            .filter(is_tls13)
+            .collect());
+    }
+    let mut suites = Vec::new();
+    for name in names {
+        let suite = rustls::ALL_CIPHER_SUITES
+            .iter()
+            .find(|suite| suite_key(&suite_name(suite.suite())) == suite_key(name))
+            .ok_or_else(|| format!("unknown cipher suite {}", name))?;
+        if !is_tls13(suite) {
+            Err(format!(
+                "{} is not a TLS 1.3 suite, QUIC requires TLS 1.3",
+                name
+            ))?;
+        }
+        suites.push(*suite);
+    }
+    Ok(suites)
+}
+
+/// It returns the key exchange groups with the given names (x25519, secp256r1 or p-256,
+/// secp384r1 or p-384), in the given order. Without names, all the rustls groups are used.
+pub fn kx_groups(names: &[String]) -> Result<Vec<&'static SupportedKxGroup>, Box<dyn Error>> {
+    if names.is_empty() {
+        return Ok(rustls::ALL_KX_GROUPS.to_vec());
+    }
+    let mut groups = Vec::new();
+    for name in names {
+        let key = match name.trim().to_uppercase().replace('-', "").as_str() {
+            "P256" => String::from("SECP256R1"),
+            "P384" => String::from("SECP384R1"),
+            other => other.to_string(),
+        };
+        let group = rustls::ALL_KX_GROUPS
+            .iter()
+            .find(|group| format!("{:?}", group.name).to_uppercase() == key)
+            .ok_or_else(|| format!("unknown key exchange group {}", name))?;
+        groups.push(*group);
+    }
+    Ok(groups)
+}
+
+/// The rustls name of a cipher suite, e.g. TLS13_AES_128_GCM_SHA256.
+pub fn suite_name(suite: CipherSuite) -> String {
+    format!("{:?}", suite)
+}
+
+/// The rustls name of a key exchange group, e.g. X25519.
+pub fn group_name(group: &SupportedKxGroup) -> String {
+    format!("{:?}", group.name)
+}
+
+fn is_tls13(suite: &SupportedCipherSuite) -> bool {
+    matches!(suite, SupportedCipherSuite::Tls13(_))
+}
+
+/// The part of a suite name that doesn't depend on the spelling.
+fn suite_key(name: &str) -> String {
+    let name = name.trim().to_uppercase().replace('-', "_");
+    let name = name
+        .strip_prefix("TLS13_")
+        .or_else(|| name.strip_prefix("TLS_"))
+        .unwrap_or(&name);
+    name.to_string()
+}
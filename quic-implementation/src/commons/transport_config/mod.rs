@@ -0,0 +1,305 @@
+use std::error::Error;
+use std::sync::Arc;
+use std::time::Duration;
+
+use quinn::{EndpointConfig, IdleTimeout, TransportConfig, VarInt};
+use quinn_proto::congestion::{BbrConfig, CubicConfig, NewRenoConfig};
+use serde::Deserialize;
+
+/// The prefix of the environment variables that set a transport parameter, e.g.
+/// `TP_STREAM_RECEIVE_WINDOW=5120000`.
+pub const ENV_PREFIX: &str = "TP_";
+
+/// The keys of the transport parameters, the names of the variables without their prefix.
+const KEYS: [&str; 15] = [
+    "stream_receive_window",
+    "receive_window",
+    "send_window",
+    "max_concurrent_bidi_streams",
+    "max_concurrent_uni_streams",
+    "max_idle_timeout_ms",
+    "keep_alive_interval_ms",
+    "initial_rtt_ms",
+    "packet_threshold",
+    "time_threshold",
+    "datagram_receive_buffer_size",
+    "datagram_send_buffer_size",
+    "max_udp_payload_size",
+    "allow_spin",
+    "congestion_controller",
+];
+
+/// The congestion controllers quinn provides.
+pub const CONGESTION_CONTROLLERS: [&str; 3] = ["newreno", "cubic", "bbr"];
+
+/// The quinn transport knobs that can be tuned without touching the code. Every field is
+/// optional: the ones left out keep the quinn default. They are read from a TOML file and from
+/// the `TP_*` environment variables, the latter taking precedence.
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct TransportParameters {
+    /// Bytes the peer may send on a single stream before being blocked.
+    pub stream_receive_window: Option<u64>,
+    /// Bytes the peer may send across all streams before being blocked.
+    pub receive_window: Option<u64>,
+    /// Bytes we send without acknowledgement.
+    pub send_window: Option<u64>,
+    /// Incoming bidirectional streams the peer may open concurrently.
+    pub max_concurrent_bidi_streams: Option<u64>,
+    /// Incoming unidirectional streams the peer may open concurrently.
+    pub max_concurrent_uni_streams: Option<u64>,
+    /// Inactivity after which the connection is closed, in milliseconds. 0 disables it.
+    pub max_idle_timeout_ms: Option<u64>,
+    /// Inactivity after which a keep-alive is sent, in milliseconds. 0 disables it.
+    pub keep_alive_interval_ms: Option<u64>,
+    /// RTT assumed before the first sample is taken, in milliseconds.
+    pub initial_rtt_ms: Option<u64>,
+    /// Reordering in packets before a packet is declared lost.
+    pub packet_threshold: Option<u32>,
+    /// Reordering in time, as a factor of the RTT, before a packet is declared lost.
+    pub time_threshold: Option<f32>,
+    /// Bytes of incoming datagrams to buffer. 0 disables incoming datagrams.
+    pub datagram_receive_buffer_size: Option<usize>,
+    /// Bytes of outgoing datagrams to buffer.
+    pub datagram_send_buffer_size: Option<usize>,
+    /// Largest UDP payload accepted from the peer, i.e. the MTU minus the IP and UDP headers.
+    pub max_udp_payload_size: Option<u64>,
+    /// Whether the spin bit may be used.
+    pub allow_spin: Option<bool>,
+    /// The congestion controller: newreno, cubic or bbr.
+    pub congestion_controller: Option<String>,
+}
+
+impl TransportParameters {
+    /// It reads the transport parameters from the given TOML file (if the path is not empty) and
+    /// from the environment, on top of the given defaults. The values are checked by `check`.
+    pub fn load(file: &str, defaults: TransportParameters) -> Result<Self, Box<dyn Error>> {
+        let mut parameters = defaults;
+        if !file.is_empty() {
+            let from_file: TransportParameters = super::read_toml_file(file)?;
+            parameters = parameters.merge(from_file);
+        }
+        parameters = parameters.merge(TransportParameters::from_env()?);
+        Ok(parameters)
+    }
+
+    /// It reads the `TP_*` environment variables. The prefix isn't reserved to this program, so
+    /// a variable naming no transport parameter is ignored with a warning, unlike an unknown key
+    /// of the file.
+    fn from_env() -> Result<Self, Box<dyn Error>> {
+        let vars = std::env::vars().filter(|(name, _)| {
+            let known = KEYS
+                .iter()
+                .any(|key| name.strip_prefix(ENV_PREFIX) == Some(&key.to_uppercase()));
+            if name.starts_with(ENV_PREFIX) && !known {
+                println!("Ignoring {}, it names no transport parameter", name);
+            }
+            known
+        });
+        let from_env = envy::prefixed(ENV_PREFIX)
+            .from_iter(vars)
+            .map_err(|e| format!("invalid {}* environment variable: {}", ENV_PREFIX, e))?;
+        Ok(from_env)
+    }
+
+    /// Returns these parameters with the ones set in `other` replacing them.
+    pub fn merge(self, other: TransportParameters) -> Self {
+        TransportParameters {
+            stream_receive_window: other.stream_receive_window.or(self.stream_receive_window),
+            receive_window: other.receive_window.or(self.receive_window),
+            send_window: other.send_window.or(self.send_window),
+            max_concurrent_bidi_streams: other
+                .max_concurrent_bidi_streams
+                .or(self.max_concurrent_bidi_streams),
+            max_concurrent_uni_streams: other
+                .max_concurrent_uni_streams
+                .or(self.max_concurrent_uni_streams),
+            max_idle_timeout_ms: other.max_idle_timeout_ms.or(self.max_idle_timeout_ms),
+            keep_alive_interval_ms: other.keep_alive_interval_ms.or(self.keep_alive_interval_ms),
+            initial_rtt_ms: other.initial_rtt_ms.or(self.initial_rtt_ms),
+            packet_threshold: other.packet_threshold.or(self.packet_threshold),
+            time_threshold: other.time_threshold.or(self.time_threshold),
+            datagram_receive_buffer_size: other
+                .datagram_receive_buffer_size
+                .or(self.datagram_receive_buffer_size),
+            datagram_send_buffer_size: other
+                .datagram_send_buffer_size
+                .or(self.datagram_send_buffer_size),
+            max_udp_payload_size: other.max_udp_payload_size.or(self.max_udp_payload_size),
+            allow_spin: other.allow_spin.or(self.allow_spin),
+            congestion_controller: other.congestion_controller.or(self.congestion_controller),
+        }
+    }
+
+    /// It checks that the values can be encoded and are consistent with each other. Every problem
+    /// is added to `problems`, naming the offending key of the [transport] section.
+    pub fn check(&self, problems: &mut Vec<String>) {
+        let varints = [
+            ("stream_receive_window", self.stream_receive_window),
+            ("receive_window", self.receive_window),
+            (
+                "max_concurrent_bidi_streams",
+                self.max_concurrent_bidi_streams,
+            ),
+            (
+                "max_concurrent_uni_streams",
+                self.max_concurrent_uni_streams,
+            ),
+            ("max_idle_timeout_ms", self.max_idle_timeout_ms),
+        ];
+        for (key, value) in varints {
+            if let Some(value) = value {
+                if VarInt::from_u64(value).is_err() {
+                    problems.push(format!(
+                        "`transport.{}`: {} is larger than 2^62 - 1",
+                        key, value
+                    ));
+                }
+            }
+        }
+        if let (Some(stream), Some(connection)) = (self.stream_receive_window, self.receive_window)
+        {
+            if stream > connection {
+                problems.push(format!(
+                    "`transport.stream_receive_window`: {} is larger than receive_window {}",
+                    stream, connection
+                ));
+            }
+        }
+        if let (Some(keep_alive), Some(idle)) =
+            (self.keep_alive_interval_ms, self.max_idle_timeout_ms)
+        {
+            if keep_alive != 0 && idle != 0 && keep_alive >= idle {
+                problems.push(format!(
+                    "`transport.keep_alive_interval_ms`: {} is not lower than max_idle_timeout_ms {}",
+                    keep_alive, idle
+                ));
+            }
+        }
+        if self.initial_rtt_ms == Some(0) {
+            problems.push(String::from(
+                "`transport.initial_rtt_ms`: must be greater than 0",
+            ));
+        }
+        if let Some(threshold) = self.packet_threshold {
+            if threshold < 3 {
+                problems.push(format!(
+                    "`transport.packet_threshold`: {} is lower than 3",
+                    threshold
+                ));
+            }
+        }
+        if let Some(threshold) = self.time_threshold {
+            if threshold.is_nan() || threshold < 1.0 {
+                problems.push(format!(
+                    "`transport.time_threshold`: {} is lower than 1.0",
+                    threshold
+                ));
+            }
+        }
+        if let Some(controller) = &self.congestion_controller {
+            if !CONGESTION_CONTROLLERS.contains(&controller.as_str()) {
+                problems.push(format!(
+                    "`transport.congestion_controller`: {} is not one of {}",
+                    controller,
+                    CONGESTION_CONTROLLERS.join(", ")
+                ));
+            }
+        }
+        if let Some(size) = self.max_udp_payload_size {
+            if !(1200..=65527).contains(&size) {
+                problems.push(format!(
+                    "`transport.max_udp_payload_size`: {} is not between 1200 and 65527",
+                    size
+                ));
+            }
+        }
+    }
+
+    /// It sets the configured values on a quinn transport config.
+    pub fn apply(&self, transport: &mut TransportConfig) -> Result<(), Box<dyn Error>> {
+        if let Some(value) = self.stream_receive_window {
+            transport.stream_receive_window(VarInt::from_u64(value)?);
+        }
+        if let Some(value) = self.receive_window {
+            transport.receive_window(VarInt::from_u64(value)?);
+        }
+        if let Some(value) = self.send_window {
+            transport.send_window(value);
+        }
+        if let Some(value) = self.max_concurrent_bidi_streams {
+            transport.max_concurrent_bidi_streams(VarInt::from_u64(value)?);
+        }
+        if let Some(value) = self.max_concurrent_uni_streams {
+            transport.max_concurrent_uni_streams(VarInt::from_u64(value)?);
+        }
+        if let Some(value) = self.max_idle_timeout_ms {
+            let timeout = match value {
+                0 => None,
+                ms => Some(IdleTimeout::from(VarInt::from_u64(ms)?)),
+            };
+            transport.max_idle_timeout(timeout);
+        }
+        if let Some(value) = self.keep_alive_interval_ms {
+            let interval = match value {
+                0 => None,
+                ms => Some(Duration::from_millis(ms)),
+            };
+            transport.keep_alive_interval(interval);
+        }
+        if let Some(value) = self.initial_rtt_ms {
+            transport.initial_rtt(Duration::from_millis(value));
+        }
+        if let Some(value) = self.packet_threshold {
+            transport.packet_threshold(value);
+        }
+        if let Some(value) = self.time_threshold {
+            transport.time_threshold(value);
+        }
+        if let Some(value) = self.datagram_receive_buffer_size {
+            let size = match value {
+                0 => None,
+                size => Some(size),
+            };
+            transport.datagram_receive_buffer_size(size);
+        }
+        if let Some(value) = self.datagram_send_buffer_size {
+            transport.datagram_send_buffer_size(value);
+        }
+        if let Some(value) = self.allow_spin {
+            transport.allow_spin(value);
+        }
+        match self.congestion_controller.as_deref() {
+            Some("newreno") => {
+                transport.congestion_controller_factory(Arc::new(NewRenoConfig::default()));
+            }
+            Some("cubic") => {
+                transport.congestion_controller_factory(Arc::new(CubicConfig::default()));
+            }
+            Some("bbr") => {
+                transport.congestion_controller_factory(Arc::new(BbrConfig::default()));
+            }
+            Some(other) => Err(format!("unknown congestion controller {}", other))?,
+            None => {}
+        }
+        Ok(())
+    }
+
+    /// The name of the congestion controller the connections use, for the logs.
+    pub fn congestion_controller_name(&self) -> &str {
+        self.congestion_controller
+            .as_deref()
+            .unwrap_or("the quinn default")
+    }
+
+    /// It sets the configured values that belong to the endpoint rather than to a connection.
+    pub fn apply_endpoint(
+        &self,
+        endpoint_config: &mut EndpointConfig,
+    ) -> Result<(), Box<dyn Error>> {
+        if let Some(value) = self.max_udp_payload_size {
+            endpoint_config.max_udp_payload_size(value)?;
+        }
+        Ok(())
+    }
+}
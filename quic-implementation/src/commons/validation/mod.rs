@@ -0,0 +1,143 @@
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// The exit code the interop runner expects when a testcase is not supported.
+pub const UNSUPPORTED_TESTCASE_EXIT_CODE: i32 = 127;
+
+/// The exit code used when the configuration is invalid.
+pub const INVALID_CONFIG_EXIT_CODE: i32 = 1;
+
+/// Why a configuration was refused.
+#[derive(Debug)]
+pub enum ConfigError {
+    /// The testcase is not implemented. The runner requires the process to exit with 127.
+    UnsupportedTestcase(String),
+    /// Every problem found in the configuration, each one naming the offending key.
+    Invalid(Vec<String>),
+}
+
+impl ConfigError {
+    /// The exit code of the process for this error.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            ConfigError::UnsupportedTestcase(_) => UNSUPPORTED_TESTCASE_EXIT_CODE,
+            ConfigError::Invalid(_) => INVALID_CONFIG_EXIT_CODE,
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::UnsupportedTestcase(testcase) => {
+                write!(f, "unsupported testcase `{}`", testcase)
+            }
+            ConfigError::Invalid(problems) => {
+                write!(f, "invalid configuration:")?;
+                for problem in problems {
+                    write!(f, "\n  - {}", problem)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+/// It returns `Ok` if no problem was found, the whole list otherwise.
+pub fn into_result(problems: Vec<String>) -> Result<(), ConfigError> {
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(ConfigError::Invalid(problems))
+    }
+}
+
+/// It checks that `path` is a directory whose content can be listed.
+pub fn check_readable_dir(key: &str, path: &str, problems: &mut Vec<String>) {
+    if let Err(e) = fs::read_dir(path) {
+        problems.push(format!("`{}`: cannot read directory {}: {}", key, path, e));
+    }
+}
+
+/// The number of files created so far to check that a directory is writable, which makes the
+/// name of each one unique within the process.
+static WRITE_CHECKS: AtomicUsize = AtomicUsize::new(0);
+
+/// It checks that `path` is an existing directory the process can create files in, by creating
+/// and removing an empty file in it.
+pub fn check_writable_dir(key: &str, path: &str, problems: &mut Vec<String>) {
+    match fs::metadata(path) {
+        Ok(metadata) if !metadata.is_dir() => {
+            problems.push(format!("`{}`: {} is not a directory", key, path))
+        }
+        Ok(_) => {
+            let probe = Path::new(path).join(format!(
+                ".write-check-{}-{}",
+                std::process::id(),
+                WRITE_CHECKS.fetch_add(1, Ordering::Relaxed)
+            ));
+            match fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&probe)
+            {
+                Ok(_) => {
+                    let _ = fs::remove_file(&probe);
+                }
+                Err(e) => problems.push(format!(
+                    "`{}`: cannot write in directory {}: {}",
+                    key, path, e
+                )),
+            }
+        }
+        Err(e) => problems.push(format!("`{}`: cannot use directory {}: {}", key, path, e)),
+    }
+}
+
+/// It checks that the file at `path` can be created, i.e. that its directory exists.
+pub fn check_writable_file(key: &str, path: &str, problems: &mut Vec<String>) {
+    let parent = match Path::new(path).parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    if !parent.is_dir() {
+        problems.push(format!(
+            "`{}`: the directory of {} does not exist",
+            key, path
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_writable_directory_is_left_as_it_was() {
+        let dir = std::env::temp_dir().join(format!(
+            "quic-implementation-writable-{}",
+            std::process::id()
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        let mut problems = Vec::new();
+        check_writable_dir("logs", &dir.to_string_lossy(), &mut problems);
+        let left = fs::read_dir(&dir).unwrap().count();
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(problems.is_empty(), "{:?}", problems);
+        assert_eq!(left, 0);
+    }
+
+    #[test]
+    fn files_and_missing_directories_are_not_writable_directories() {
+        let mut problems = Vec::new();
+        check_writable_dir("logs", "Cargo.toml", &mut problems);
+        check_writable_dir("logs", "no/such/directory", &mut problems);
+        assert_eq!(problems.len(), 2);
+        assert!(problems[0].contains("is not a directory"));
+    }
+}
@@ -0,0 +1,251 @@
+use std::io::Write;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+use tracing_appender::rolling::{self, RollingFileAppender};
+
+use super::early_data::EarlyData;
+use super::env_parser::Config;
+
+/// The name of the access log in the logs directory, followed by the date when it is rotated.
+const FILE_NAME: &str = "access.log";
+
+pub const FORMATS: &[&str] = &["off", "common", "combined", "json"];
+pub const ROTATIONS: &[&str] = &["hourly", "daily", "never"];
+
+/// A log with one line per request, written to the logs directory.
+pub struct AccessLog {
+    format: String,
+    file: Mutex<RollingFileAppender>,
+}
+
+/// What the access log records about a request. It is filled in when the request arrives and
+/// completed once the response is sent.
+#[derive(Serialize, Debug)]
+pub struct Entry {
+    pub remote_addr: SocketAddr,
+    #[serde(skip)]
+    pub received: SystemTime,
+    pub time: String,
+    pub method: String,
+    pub path: String,
+    pub protocol: &'static str,
+    pub status: u16,
+    pub bytes: usize,
+    pub duration_ms: f64,
+    /// quinn 0.8 doesn't expose the connection IDs, this is its stable ID for the connection.
+    pub connection_id: usize,
+    pub early_data: bool,
+    pub referer: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+impl AccessLog {
+    /// It opens the access log in the logs directory, `None` if the format is off.
+    pub fn new(config: &Config) -> Option<AccessLog> {
+        if config.access_log_format == "off" {
+            return None;
+        }
+        let file = match config.access_log_rotation.as_str() {
+            "hourly" => rolling::hourly(&config.logs, FILE_NAME),
+            "never" => rolling::never(&config.logs, FILE_NAME),
+            _ => rolling::daily(&config.logs, FILE_NAME),
+        };
+        Some(AccessLog {
+            format: config.access_log_format.clone(),
+            file: Mutex::new(file),
+        })
+    }
+
+    pub fn write(&self, entry: &Entry) {
+        let line = match self.format.as_str() {
+            "json" => serde_json::to_string(entry).unwrap_or_default(),
+            "common" => entry.common(),
+            _ => format!(
+                "{} \"{}\" \"{}\"{}",
+                entry.common_fields(),
+                escape_quotes(entry.referer.as_deref().unwrap_or("-")),
+                escape_quotes(entry.user_agent.as_deref().unwrap_or("-")),
+                entry.extra_fields()
+            ),
+        };
+        if let Err(err) = writeln!(self.file.lock().unwrap(), "{}", line) {
+            println!("Unable to write the access log: {:?}", err);
+        }
+    }
+}
+
+impl Entry {
+    pub fn new(req: &http::Request<()>, remote_addr: SocketAddr, connection_id: usize) -> Entry {
+        let header = |name| {
+            req.headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(String::from)
+        };
+        Entry {
+            remote_addr,
+            received: SystemTime::now(),
+            time: String::new(),
+            method: req.method().to_string(),
+            path: req
+                .uri()
+                .path_and_query()
+                .map(|path| path.to_string())
+                .unwrap_or_else(|| String::from("/")),
+            protocol: "HTTP/3",
+            status: 0,
+            bytes: 0,
+            duration_ms: 0.0,
+            connection_id,
+            early_data: req.extensions().get::<EarlyData>().is_some(),
+            referer: header(http::header::REFERER),
+            user_agent: header(http::header::USER_AGENT),
+        }
+    }
+
+    pub fn complete(&mut self, status: http::StatusCode, bytes: usize, duration: Duration) {
+        self.time = rfc3339(self.received);
+        self.status = status.as_u16();
+        self.bytes = bytes;
+        self.duration_ms = duration.as_secs_f64() * 1000.0;
+    }
+
+    fn common(&self) -> String {
+        format!("{}{}", self.common_fields(), self.extra_fields())
+    }
+
+    /// host ident authuser [date] "request line" status bytes
+    fn common_fields(&self) -> String {
+        format!(
+            "{} - - [{}] \"{} {} {}\" {} {}",
+            self.remote_addr.ip(),
+            clf_time(self.received),
+            self.method,
+            escape_quotes(&self.path),
+            self.protocol,
+            self.status,
+            if self.bytes == 0 {
+                String::from("-")
+            } else {
+                self.bytes.to_string()
+            }
+        )
+    }
+
+    /// The fields the Common Log Format has no place for, appended to the line.
+    fn extra_fields(&self) -> String {
+        format!(
+            " {:.3} {} {}",
+            self.duration_ms,
+            self.connection_id,
+            if self.early_data { "0-RTT" } else { "-" }
+        )
+    }
+}
+
+/// A value written between quotes, with its own quotes percent-encoded so that it can't end the
+/// field early.
+fn escape_quotes(value: &str) -> String {
+    value.replace('"', "%22")
+}
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// It formats a time as 19/Oct/2026:13:55:36 +0000.
+fn clf_time(time: SystemTime) -> String {
+    let (year, month, day, seconds, _) = utc(time);
+    format!(
+        "{:02}/{}/{}:{:02}:{:02}:{:02} +0000",
+        day,
+        MONTHS[month as usize - 1],
+        year,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+/// It formats a time as 2026-10-19T13:55:36.123Z.
+fn rfc3339(time: SystemTime) -> String {
+    let (year, month, day, seconds, millis) = utc(time);
+    format!(
+        "{}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60,
+        millis
+    )
+}
+
+/// The UTC date of a time, the seconds since midnight and the milliseconds.
+fn utc(time: SystemTime) -> (i64, u32, u32, u64, u32) {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let days = (since_epoch.as_secs() / 86400) as i64;
+    // The civil from days algorithm of Howard Hinnant
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (
+        year,
+        month,
+        day,
+        since_epoch.as_secs() % 86400,
+        since_epoch.subsec_millis(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn utc_of_the_epoch() {
+        assert_eq!(utc(UNIX_EPOCH), (1970, 1, 1, 0, 0));
+    }
+
+    #[test]
+    fn utc_of_a_leap_day() {
+        // 2024-02-29T23:59:59.250Z
+        let time = UNIX_EPOCH + Duration::from_millis(1_709_251_199_250);
+        assert_eq!(utc(time), (2024, 2, 29, 86399, 250));
+    }
+
+    #[test]
+    fn utc_after_the_end_of_february() {
+        // 2100-03-01T00:00:00Z, 2100 isn't a leap year
+        let time = UNIX_EPOCH + Duration::from_secs(4_107_542_400);
+        assert_eq!(utc(time), (2100, 3, 1, 0, 0));
+    }
+
+    #[test]
+    fn clf_time_pads_the_fields() {
+        // 2026-10-09T08:05:03Z
+        let time = UNIX_EPOCH + Duration::from_secs(1_791_533_103);
+        assert_eq!(clf_time(time), "09/Oct/2026:08:05:03 +0000");
+    }
+
+    #[test]
+    fn rfc3339_keeps_the_milliseconds() {
+        let time = UNIX_EPOCH + Duration::from_millis(1_791_533_103_007);
+        assert_eq!(rfc3339(time), "2026-10-09T08:05:03.007Z");
+    }
+
+    #[test]
+    fn quotes_are_escaped() {
+        assert_eq!(escape_quotes(r#"a "quoted" agent"#), "a %22quoted%22 agent");
+    }
+}
@@ -0,0 +1,88 @@
+use h3_quinn::quinn::Connection;
+
+use super::super::commons::certificates;
+use super::env_parser::AccessRule;
+
+/// The identity of a client that authenticated with a certificate: the common name of its leaf.
+/// It is stored in the extensions of the requests of the connection.
+#[derive(Debug, Clone)]
+pub struct ClientIdentity(pub String);
+
+impl ClientIdentity {
+    /// It returns the identity of the peer of the connection, if it sent a certificate with a
+    /// common name.
+    pub fn of(connection: &Connection) -> Option<ClientIdentity> {
+        let chain = connection
+            .peer_identity()?
+            .downcast::<Vec<rustls::Certificate>>()
+            .ok()?;
+        certificates::common_name(&chain.first()?.0).map(ClientIdentity)
+    }
+}
+
+/// Whether the client may get the path. The longest rule matching the path decides, a path no
+/// rule matches is public.
+pub fn is_authorized(rules: &[AccessRule], path: &str, identity: Option<&ClientIdentity>) -> bool {
+    let rule = rules
+        .iter()
+        .filter(|rule| matches(&rule.path, path))
+        .max_by_key(|rule| rule.path.len());
+    match (rule, identity) {
+        (None, _) => true,
+        (Some(rule), Some(identity)) => rule.clients.contains(&identity.0),
+        (Some(_), None) => false,
+    }
+}
+
+/// Whether the path is under the prefix, comparing whole segments.
+fn matches(prefix: &str, path: &str) -> bool {
+    let prefix = prefix.trim_end_matches('/');
+    match path.strip_prefix(prefix) {
+        Some(rest) => rest.is_empty() || rest.starts_with('/'),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(path: &str, clients: &[&str]) -> AccessRule {
+        AccessRule {
+            path: String::from(path),
+            clients: clients.iter().map(|&client| String::from(client)).collect(),
+        }
+    }
+
+    #[test]
+    fn prefixes_match_whole_segments() {
+        assert!(matches("/private", "/private"));
+        assert!(matches("/private", "/private/report.txt"));
+        assert!(matches("/private/", "/private/report.txt"));
+        assert!(!matches("/private", "/privateer"));
+        assert!(!matches("/private", "/public/private"));
+    }
+
+    #[test]
+    fn the_root_matches_every_path() {
+        assert!(matches("/", "/index.html"));
+    }
+
+    #[test]
+    fn the_longest_rule_decides() {
+        let rules = [rule("/private", &["alice"]), rule("/private/bob", &["bob"])];
+        let alice = ClientIdentity(String::from("alice"));
+        let bob = ClientIdentity(String::from("bob"));
+        assert!(is_authorized(&rules, "/private/a.txt", Some(&alice)));
+        assert!(!is_authorized(&rules, "/private/a.txt", Some(&bob)));
+        assert!(!is_authorized(&rules, "/private/bob/a.txt", Some(&alice)));
+        assert!(is_authorized(&rules, "/private/bob/a.txt", Some(&bob)));
+    }
+
+    #[test]
+    fn paths_no_rule_matches_are_public() {
+        let rules = [rule("/private", &["alice"])];
+        assert!(is_authorized(&rules, "/index.html", None));
+        assert!(!is_authorized(&rules, "/private", None));
+    }
+}
@@ -0,0 +1,155 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime};
+
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use tokio::signal::unix::{signal, SignalKind};
+
+use super::certs_configuration;
+use super::env_parser::VirtualHost;
+
+/// How often the certs directories are checked for a new key pair.
+const WATCH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// A certificate resolver picking the key pair of the virtual host named by the SNI, or the
+/// default one. Every pair can be replaced while the server is running: new handshakes use the
+/// latest pair that was loaded successfully, the ones in progress keep the pair they started with.
+pub struct CertResolver {
+    default: KeyPair,
+    hosts: HashMap<String, KeyPair>,
+}
+
+/// The key pair of one certs directory.
+struct KeyPair {
+    certs_dir: PathBuf,
+    passphrase_file: String,
+    current: RwLock<Arc<CertifiedKey>>,
+    last_modified: Mutex<[Option<SystemTime>; 2]>,
+}
+
+impl CertResolver {
+    /// It loads cert.pem and priv.key from the default certs directory and from the one of every
+    /// virtual host, decrypting the keys with `passphrase_file` if needed.
+    pub fn new(
+        certs_dir: &str,
+        passphrase_file: &str,
+        virtual_hosts: &[VirtualHost],
+    ) -> Result<CertResolver, Box<dyn Error>> {
+        let mut hosts = HashMap::new();
+        for host in virtual_hosts {
+            hosts.insert(
+                host.name.clone(),
+                KeyPair::new(&host.certs, passphrase_file)?,
+            );
+        }
+        Ok(CertResolver {
+            default: KeyPair::new(certs_dir, passphrase_file)?,
+            hosts,
+        })
+    }
+
+    /// It spawns a task reloading a key pair when its cert.pem or priv.key change, and all of
+    /// them when the process receives SIGHUP.
+    pub fn watch(self: Arc<Self>) {
+        tokio::spawn(async move {
+            let mut hangup = match signal(SignalKind::hangup()) {
+                Ok(hangup) => Some(hangup),
+                Err(err) => {
+                    println!("Unable to listen for SIGHUP: {:?}", err);
+                    None
+                }
+            };
+            let mut ticker = tokio::time::interval(WATCH_INTERVAL);
+            loop {
+                tokio::select! {
+                    _ = ticker.tick() => {
+                        for key_pair in self.key_pairs().filter(|key_pair| key_pair.changed()) {
+                            println!("Certificates changed in {:?}, reloading", key_pair.certs_dir);
+                            key_pair.reload();
+                        }
+                    }
+                    Some(_) = recv(&mut hangup) => {
+                        println!("SIGHUP received, reloading certificates");
+                        for key_pair in self.key_pairs() {
+                            key_pair.reload();
+                        }
+                    }
+                }
+            }
+        });
+    }
+
+    fn key_pairs(&self) -> impl Iterator<Item = &KeyPair> {
+        std::iter::once(&self.default).chain(self.hosts.values())
+    }
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        let key_pair = client_hello
+            .server_name()
+            .and_then(|name| self.hosts.get(&name.to_lowercase()))
+            .unwrap_or(&self.default);
+        Some(key_pair.current.read().unwrap().clone())
+    }
+}
+
+impl KeyPair {
+    fn new(certs_dir: &str, passphrase_file: &str) -> Result<KeyPair, Box<dyn Error>> {
+        let certs_dir = PathBuf::from(certs_dir);
+        let certified_key = certs_configuration::load_certified_key(&certs_dir, passphrase_file)?;
+        Ok(KeyPair {
+            passphrase_file: passphrase_file.to_string(),
+            last_modified: Mutex::new(modified(&certs_dir)),
+            current: RwLock::new(Arc::new(certified_key)),
+            certs_dir,
+        })
+    }
+
+    /// It loads the key pair again and swaps it in. If the new pair can't be used, the old one
+    /// is kept.
+    fn reload(&self) {
+        match certs_configuration::load_certified_key(&self.certs_dir, &self.passphrase_file) {
+            Ok(certified_key) => {
+                *self.current.write().unwrap() = Arc::new(certified_key);
+                println!("Certificates reloaded from {:?}", self.certs_dir);
+            }
+            Err(err) => println!(
+                "Unable to reload certificates from {:?}, keeping the old ones: {}",
+                self.certs_dir, err
+            ),
+        }
+    }
+
+    /// Whether the files changed since the last call.
+    fn changed(&self) -> bool {
+        let modified = modified(&self.certs_dir);
+        let mut last_modified = self.last_modified.lock().unwrap();
+        if *last_modified == modified {
+            return false;
+        }
+        *last_modified = modified;
+        true
+    }
+}
+
+/// The modification times of the key pair files, `None` for a missing file.
+fn modified(certs_dir: &Path) -> [Option<SystemTime>; 2] {
+    ["cert.pem", "priv.key"].map(|name| {
+        fs::metadata(certs_dir.join(name))
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    })
+}
+
+/// Waits for the next signal, forever if there is no signal stream.
+async fn recv(signal: &mut Option<tokio::signal::unix::Signal>) -> Option<()> {
+    match signal {
+        Some(signal) => signal.recv().await,
+        None => futures::future::pending().await,
+    }
+}
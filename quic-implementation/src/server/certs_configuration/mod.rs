@@ -1,81 +1,153 @@
 use std::error::Error;
 use std::sync::Arc;
+use std::time::Duration;
 use std::{fs, path::Path};
 
-use rustls::cipher_suite::TLS13_CHACHA20_POLY1305_SHA256;
-use rustls::{Certificate, PrivateKey, ServerConfig};
+use rustls::server::{
+    AllowAnyAnonymousOrAuthenticatedClient, AllowAnyAuthenticatedClient, ClientCertVerifier,
+    NoClientAuth,
+};
+use rustls::sign::{CertifiedKey, SigningKey};
+use rustls::{Certificate, PrivateKey, ServerConfig, SignatureScheme};
 
 use super::super::commons;
+use super::super::commons::certificates::{self, KeyError, KeyFormat};
+use super::super::commons::tls_config;
+use super::cert_resolver::CertResolver;
 use super::env_parser::Config;
+use super::tickets::Ticketer;
 
 pub fn get_server_crypto(config: &Config) -> Result<ServerConfig, Box<dyn Error>> {
-    let (certs, key) = parse_certificates(config)?;
+    let cipher_suites = tls_config::cipher_suites(&config.cipher_suites, &config.testcase)?;
+    let kx_groups = tls_config::kx_groups(&config.kx_groups)?;
+    println!(
+        "TLS cipher suites: {:?}, key exchange groups: {:?}",
+        cipher_suites
+            .iter()
+            .map(|suite| tls_config::suite_name(suite.suite()))
+            .collect::<Vec<_>>(),
+        kx_groups
+            .iter()
+            .map(|group| tls_config::group_name(group))
+            .collect::<Vec<_>>()
+    );
+    let resolver = Arc::new(CertResolver::new(
+        &config.certs,
+        &config.key_passphrase_file,
+        &config.virtual_hosts,
+    )?);
+    resolver.clone().watch();
+    let client_cert_verifier = get_client_cert_verifier(config)?;
 
-    let mut server_crypto = if config.testcase == "chacha20" {
-        let cipher_suites = [TLS13_CHACHA20_POLY1305_SHA256];
-        rustls::ServerConfig::builder()
-            .with_cipher_suites(&cipher_suites)
-            .with_safe_default_kx_groups()
-            .with_protocol_versions(&[&rustls::version::TLS13])
-            .unwrap()
-            .with_no_client_auth()
-            .with_single_cert(certs, key)?
-    } else {
-        rustls::ServerConfig::builder()
-            .with_safe_default_cipher_suites()
-            .with_safe_default_kx_groups()
-            .with_protocol_versions(&[&rustls::version::TLS13])
-            .unwrap()
-            .with_no_client_auth()
-            .with_single_cert(certs, key)?
-    };
+    let mut server_crypto = rustls::ServerConfig::builder()
+        .with_cipher_suites(&cipher_suites)
+        .with_kx_groups(&kx_groups)
+        .with_protocol_versions(&[&rustls::version::TLS13])?
+        .with_client_cert_verifier(client_cert_verifier)
+        .with_cert_resolver(resolver);
 
-    server_crypto.max_early_data_size = u32::MAX;
+    server_crypto.ticketer = Arc::new(
+        Ticketer::new(
+            Duration::from_secs(config.ticket_lifetime_s.into()),
+            Duration::from_secs(config.ticket_rotation_s.into()),
+            config.anti_replay_capacity,
+        )
+        .map_err(|_| "unable to generate a session ticket key")?,
+    );
+    // QUIC only allows 0 or 2^32 - 1
+    server_crypto.max_early_data_size = if config.early_data { u32::MAX } else { 0 };
     server_crypto.alpn_protocols = vec![commons::ALPN.into()];
     server_crypto.key_log = Arc::new(rustls::KeyLogFile::new());
 
     Ok(server_crypto)
 }
 
-fn parse_certificates(config: &Config) -> Result<(Vec<Certificate>, PrivateKey), Box<dyn Error>> {
-    let certs_dir = Path::new(&config.certs);
-    let cert_path = certs_dir.join("cert.pem");
-    let key_path = certs_dir.join("priv.key");
-
-    let (cert_chain, key) = fs::read(&cert_path).and_then(|x| Ok((x, fs::read(&key_path)?)))?;
-    parse_pem(cert_chain, key)
+/// It builds the verifier of the client certificates for the configured client authentication
+/// mode: none, request (anonymous clients are accepted too) or require.
+fn get_client_cert_verifier(
+    config: &Config,
+) -> Result<Arc<dyn ClientCertVerifier>, Box<dyn Error>> {
+    if config.client_auth == "none" {
+        return Ok(NoClientAuth::new());
+    }
+    let roots = certificates::read_roots(&config.client_ca)?;
+    let verifier = if config.client_auth == "require" {
+        AllowAnyAuthenticatedClient::new(roots)
+    } else {
+        AllowAnyAnonymousOrAuthenticatedClient::new(roots)
+    };
+    Ok(verifier)
 }
 
-fn parse_pem(
-    cert: Vec<u8>,
-    private_key: Vec<u8>,
-) -> Result<(Vec<Certificate>, PrivateKey), Box<dyn Error>> {
-    let parsed_key = parse_pem_key(private_key)?;
-    let parsed_certs = parse_pem_cert(cert)?;
-
-    Ok((parsed_certs, parsed_key))
+/// It reads the key pair from the certs directory and checks that the key belongs to the leaf
+/// certificate, so that a half rotated pair is never served. An encrypted key is decrypted with
+/// the passphrase of `passphrase_file`.
+pub fn load_certified_key(
+    certs_dir: &Path,
+    passphrase_file: &str,
+) -> Result<CertifiedKey, Box<dyn Error>> {
+    let (cert_chain, key, format) = parse_certificates(certs_dir, passphrase_file)?;
+    let signing_key =
+        rustls::sign::any_supported_type(&key).map_err(|_| "unsupported private key type")?;
+    check_key_matches_cert(&cert_chain[0], signing_key.as_ref(), format)?;
+    Ok(CertifiedKey::new(cert_chain, signing_key))
 }
 
-fn parse_pem_cert(cert: Vec<u8>) -> Result<Vec<Certificate>, Box<dyn Error>> {
-    let v: Vec<Certificate> = rustls_pemfile::certs(&mut &*cert)?
-        .into_iter()
-        .map(rustls::Certificate)
-        .collect();
+/// It signs a probe message with the key and verifies the signature with the public key of the
+/// certificate.
+fn check_key_matches_cert(
+    cert: &Certificate,
+    key: &dyn SigningKey,
+    format: KeyFormat,
+) -> Result<(), Box<dyn Error>> {
+    let schemes = [
+        (
+            SignatureScheme::ECDSA_NISTP256_SHA256,
+            &webpki::ECDSA_P256_SHA256,
+        ),
+        (
+            SignatureScheme::ECDSA_NISTP384_SHA384,
+            &webpki::ECDSA_P384_SHA384,
+        ),
+        (SignatureScheme::ED25519, &webpki::ED25519),
+        (
+            SignatureScheme::RSA_PSS_SHA256,
+            &webpki::RSA_PSS_2048_8192_SHA256_LEGACY_KEY,
+        ),
+    ];
+    let offered: Vec<SignatureScheme> = schemes.iter().map(|(scheme, _)| *scheme).collect();
+    let signer = key
+        .choose_scheme(&offered)
+        .ok_or("the private key supports none of the usual signature schemes")?;
+    let algorithm = schemes
+        .iter()
+        .find(|(scheme, _)| *scheme == signer.scheme())
+        .map(|(_, algorithm)| *algorithm)
+        .ok_or("the private key chose an unexpected signature scheme")?;
 
-    Ok(v)
+    let probe = b"quic-implementation key pair check";
+    let signature = signer.sign(probe)?;
+    let leaf = webpki::EndEntityCert::try_from(&cert.0[..])
+        .map_err(|e| format!("invalid leaf certificate: {:?}", e))?;
+    leaf.verify_signature(algorithm, probe, &signature)
+        .map_err(|_| KeyError::Mismatch(format))?;
+    Ok(())
 }
 
-fn parse_pem_key(key: Vec<u8>) -> Result<PrivateKey, Box<dyn Error>> {
-    let pkcs8: Vec<Vec<u8>> = rustls_pemfile::pkcs8_private_keys(&mut &*key)?;
-    let key = match pkcs8.into_iter().next() {
-        Some(x) => PrivateKey(x),
-        None => {
-            let rsa = rustls_pemfile::rsa_private_keys(&mut &*key)?;
-            match rsa.into_iter().next() {
-                Some(x) => PrivateKey(x),
-                None => panic!("no private key found"),
-            }
-        }
-    };
-    Ok(key)
+/// It reads cert.pem and priv.key from the certs directory and checks that the key can sign.
+/// Both files may be PEM or DER.
+pub fn parse_certificates(
+    certs_dir: &Path,
+    passphrase_file: &str,
+) -> Result<(Vec<Certificate>, PrivateKey, KeyFormat), Box<dyn Error>> {
+    let cert_path = certs_dir.join("cert.pem");
+    let key_path = certs_dir.join("priv.key");
+
+    let (cert_chain, key) = fs::read(&cert_path).and_then(|x| Ok((x, fs::read(&key_path)?)))?;
+    let (key, format) = certificates::parse_private_key(&key, passphrase_file)?;
+    let cert_chain = certificates::parse_certs(&cert_chain)?;
+    if cert_chain.is_empty() {
+        Err("no certificate found in cert.pem")?;
+    }
+    Ok((cert_chain, key, format))
 }
@@ -0,0 +1,96 @@
+use h3_quinn::quinn::{Connecting, Connection, ConnectionError, NewConnection};
+
+use super::super::commons::handshake;
+
+/// The label of the keying material exported to find out whether the handshake is complete.
+const HANDSHAKE_PROBE_LABEL: &[u8] = b"EXPORTER-quic-implementation-handshake-probe";
+
+/// It marks a request received in 0-RTT data, before the handshake was complete. It is stored
+/// in the extensions of the request.
+#[derive(Debug, Clone, Copy)]
+pub struct EarlyData;
+
+/// Whether the handshake of a connection is complete. Requests accepted before are early data.
+///
+/// It asks the TLS session itself, which can only export keying material once it has processed
+/// the client Finished. quinn processes a packet before it hands out the streams opened by the
+/// packets that follow, so a 1-RTT request arriving together with the Finished is never taken
+/// for early data. A replayed 0-RTT flight can't complete the handshake, so its requests always
+/// are.
+pub fn is_handshake_complete(connection: &Connection) -> bool {
+    connection
+        .export_keying_material(&mut [0u8; 1], HANDSHAKE_PROBE_LABEL, b"")
+        .is_ok()
+}
+
+/// 425 Too Early (RFC 8470), which the http crate has no constant for.
+pub fn too_early() -> http::StatusCode {
+    http::StatusCode::from_u16(425).unwrap()
+}
+
+/// Whether the method is idempotent (RFC 9110), so that a replayed request does no harm.
+pub fn is_idempotent(method: &http::Method) -> bool {
+    matches!(
+        *method,
+        http::Method::GET
+            | http::Method::HEAD
+            | http::Method::OPTIONS
+            | http::Method::TRACE
+            | http::Method::PUT
+            | http::Method::DELETE
+    )
+}
+
+/// It waits for the connection to be usable. With `accept_early`, that is as soon as the
+/// ClientHello is processed, so that 0-RTT requests are served before the handshake completes;
+/// otherwise it is at the end of the handshake. `on_handshake` is called once the handshake is
+/// over, with whether 0-RTT data was accepted, or `None` if the handshake failed, which can
+/// happen after the connection was returned.
+pub async fn establish(
+    mut connecting: Connecting,
+    accept_early: bool,
+    on_handshake: impl FnOnce(Option<bool>) + Send + 'static,
+) -> Result<NewConnection, ConnectionError> {
+    if accept_early {
+        // The SNI has to be known to pick the virtual host
+        if let Err(err) = connecting.handshake_data().await {
+            on_handshake(None);
+            return Err(err);
+        }
+        connecting = match connecting.into_0rtt() {
+            Ok((conn, accepted)) => {
+                let connection = conn.connection.clone();
+                tokio::spawn(async move {
+                    // It also resolves when the connection is lost during the handshake
+                    let accepted = accepted.await;
+                    if is_handshake_complete(&connection) {
+                        println!(
+                            "Handshake complete, 0-RTT accepted: {}, {}",
+                            accepted,
+                            handshake::describe(&connection)
+                        );
+                        on_handshake(Some(accepted));
+                    } else {
+                        on_handshake(None);
+                    }
+                });
+                return Ok(conn);
+            }
+            Err(connecting) => connecting,
+        };
+    }
+    match connecting.await {
+        Ok(conn) => {
+            println!(
+                "Handshake complete, {}",
+                handshake::describe(&conn.connection)
+            );
+            on_handshake(Some(false));
+            Ok(conn)
+        }
+        Err(err) => {
+            on_handshake(None);
+            Err(err)
+        }
+    }
+}
@@ -1,26 +1,267 @@
-use std::error::Error;
+use std::collections::HashSet;
+use std::fs;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
 use serde::Deserialize;
+use structopt::StructOpt;
 
-fn mucco() -> String {
-    String::from("")
+use super::access_log;
+use super::certs_configuration;
+use super::super::commons;
+use super::super::commons::certificates;
+use super::super::commons::config;
+use super::super::commons::tls_config;
+use super::super::commons::transport_config::TransportParameters;
+use super::super::commons::validation::{self, ConfigError};
+
+/// The command line of the server. Every flag falls back to the environment variable the
+/// interop runner sets, and then to the configuration file, so the runner keeps working
+/// without passing any argument.
+#[derive(StructOpt, Debug, Default)]
+#[structopt(name = "server", about = "HTTP/3 server for the QUIC interop runner")]
+struct CliConfig {
+    /// TOML configuration file, overridden by environment variables and flags
+    #[structopt(long, env = "CONFIG_FILE")]
+    config_file: Option<String>,
+    /// File the TLS secrets are logged to, in the NSS Key Log format
+    #[structopt(long, env = "SSLKEYLOGFILE")]
+    sslkeylogfile: Option<String>,
+    /// Directory for the qlog output
+    #[structopt(long, env = "QLOGDIR")]
+    qlogdir: Option<String>,
+    /// Directory for the general logs
+    #[structopt(long, env = "LOGS")]
+    logs: Option<String>,
+    /// Format of the access log: off, common, combined or json
+    #[structopt(long, env = "ACCESS_LOG_FORMAT")]
+    access_log_format: Option<String>,
+    /// How often the access log is rotated: hourly, daily or never
+    #[structopt(long, env = "ACCESS_LOG_ROTATION")]
+    access_log_rotation: Option<String>,
+    /// Name of the interop testcase
+    #[structopt(long, env = "TESTCASE")]
+    testcase: Option<String>,
+    /// Directory the files are served from
+    #[structopt(long, env = "WWW")]
+    www: Option<String>,
+    /// Directory containing cert.pem and priv.key, a self-signed certificate is generated if it
+    /// is not set
+    #[structopt(long, env = "CERTS")]
+    certs: Option<String>,
+    /// File whose first line is the passphrase of encrypted private keys
+    #[structopt(long, env = "KEY_PASSPHRASE_FILE")]
+    key_passphrase_file: Option<String>,
+    /// Comma separated host names of the generated self-signed certificate
+    #[structopt(long, env = "SELF_SIGNED_SANS")]
+    self_signed_sans: Option<String>,
+    /// Directory the generated self-signed certificate is kept in across runs
+    #[structopt(long, env = "SELF_SIGNED_DIR")]
+    self_signed_dir: Option<String>,
+    /// IP to listen on
+    #[structopt(long, env = "IP")]
+    ip: Option<String>,
+    /// Port to listen on
+    #[structopt(long, env = "PORT")]
+    port: Option<u16>,
+    /// Comma separated list of accepted QUIC versions, e.g. 0x00000001,0xff00001d
+    #[structopt(long, env = "QUIC_VERSIONS")]
+    quic_versions: Option<String>,
+    /// TOML file with the transport parameters
+    #[structopt(long, env = "TRANSPORT_CONFIG")]
+    transport_config: Option<String>,
+    /// Maximum number of concurrent connections
+    #[structopt(long, env = "MAX_CONNECTIONS")]
+    max_connections: Option<u32>,
+    /// File holding the secret retry tokens are encrypted with, created if missing
+    #[structopt(long, env = "RETRY_TOKEN_KEY_FILE")]
+    retry_token_key_file: Option<String>,
+    /// Seconds a retry token is valid for
+    #[structopt(long, env = "RETRY_TOKEN_LIFETIME_S")]
+    retry_token_lifetime_s: Option<u64>,
+    /// Connections per second above which clients have to go through a retry
+    #[structopt(long, env = "RETRY_RATE_THRESHOLD")]
+    retry_rate_threshold: Option<u32>,
+    /// Client certificates: none, request or require
+    #[structopt(long, env = "CLIENT_AUTH")]
+    client_auth: Option<String>,
+    /// PEM bundle of the CAs client certificates are verified with
+    #[structopt(long, env = "CLIENT_CA")]
+    client_ca: Option<String>,
+    /// Comma separated TLS 1.3 cipher suites in order of preference, e.g. TLS_AES_256_GCM_SHA384
+    #[structopt(long, env = "CIPHER_SUITES")]
+    cipher_suites: Option<String>,
+    /// Comma separated key exchange groups in order of preference, e.g. x25519,secp256r1
+    #[structopt(long, env = "KX_GROUPS")]
+    kx_groups: Option<String>,
+    /// Seconds a session ticket can be used for
+    #[structopt(long, env = "TICKET_LIFETIME_S")]
+    ticket_lifetime_s: Option<u32>,
+    /// Seconds after which the session ticket key is replaced
+    #[structopt(long, env = "TICKET_ROTATION_S")]
+    ticket_rotation_s: Option<u32>,
+    /// Whether 0-RTT data is accepted, true or false, by default only for the zerortt testcase
+    #[structopt(long, env = "EARLY_DATA")]
+    early_data: Option<bool>,
+    /// Number of used session tickets remembered to refuse their replay
+    #[structopt(long, env = "ANTI_REPLAY_CAPACITY")]
+    anti_replay_capacity: Option<usize>,
+    /// Whether the generated responses under /__gen/ are served, true or false
+    #[structopt(long, env = "GEN_ROUTES")]
+    gen_routes: Option<bool>,
+    /// Address of the plain HTTP listener serving /metrics, e.g. 127.0.0.1:9090
+    #[structopt(long, env = "METRICS_ADDRESS")]
+    metrics_address: Option<String>,
+}
+
+/// The content of the configuration file. Every key is optional and is overridden by the
+/// corresponding environment variable or flag.
+///
+/// ```toml
+/// testcase = "transfer"
+/// www = "./www"
+/// gen_routes = true
+///
+/// [listen]
+/// ip = "::"
+/// port = 443
+///
+/// [quic]
+/// versions = "0x00000001"
+///
+/// [tls]
+/// certs = "./certs"
+/// key_passphrase_file = "./certs/priv.pass"
+/// # or, without certs, a generated certificate
+/// self_signed_sans = ["localhost", "example.org"]
+/// self_signed_dir = "./tmp/self_signed"
+/// sslkeylogfile = "./tmp/ssl_key_log"
+/// client_auth = "request"
+/// client_ca = "./certs/clients.pem"
+/// cipher_suites = ["TLS_AES_128_GCM_SHA256", "TLS_CHACHA20_POLY1305_SHA256"]
+/// kx_groups = ["x25519", "secp256r1"]
+/// ticket_lifetime_s = 7200
+/// early_data = true
+///
+/// [logging]
+/// logs = "./tmp/logs"
+/// access_log_format = "json"
+/// access_log_rotation = "hourly"
+///
+/// [limits]
+/// max_connections = 1000
+///
+/// [retry]
+/// token_key_file = "./tmp/retry_token_key"
+/// rate_threshold = 100
+///
+/// [transport]
+/// max_concurrent_bidi_streams = 10
+///
+/// [metrics]
+/// address = "127.0.0.1:9090"
+///
+/// [[virtual_hosts]]
+/// name = "example.org"
+/// certs = "./certs/example.org"
+/// www = "./www/example.org"
+///
+/// [[access]]
+/// path = "/private"
+/// clients = ["alice"]
+/// ```
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+struct FileConfig {
+    testcase: Option<String>,
+    www: Option<String>,
+    gen_routes: Option<bool>,
+    listen: ListenSection,
+    quic: config::QuicSection,
+    tls: TlsSection,
+    logging: LoggingSection,
+    limits: LimitsSection,
+    retry: RetrySection,
+    metrics: MetricsSection,
+    transport: TransportParameters,
+    virtual_hosts: Vec<VirtualHost>,
+    access: Vec<AccessRule>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+struct ListenSection {
+    ip: Option<String>,
+    port: Option<u16>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+struct TlsSection {
+    certs: Option<String>,
+    key_passphrase_file: Option<String>,
+    sslkeylogfile: Option<String>,
+    client_auth: Option<String>,
+    client_ca: Option<String>,
+    self_signed_sans: Option<Vec<String>>,
+    self_signed_dir: Option<String>,
+    cipher_suites: Option<Vec<String>>,
+    kx_groups: Option<Vec<String>>,
+    ticket_lifetime_s: Option<u32>,
+    ticket_rotation_s: Option<u32>,
+    early_data: Option<bool>,
+    anti_replay_capacity: Option<usize>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+struct LoggingSection {
+    logs: Option<String>,
+    qlogdir: Option<String>,
+    access_log_format: Option<String>,
+    access_log_rotation: Option<String>,
 }
 
-#[derive(Deserialize, Debug)]
-struct EnvConfig {
-    sslkeylogfile: String,
-    #[serde(default = "mucco")]
-    qlogdir: String,
-    logs: String,
-    testcase: String,
-    www: String,
-    certs: String,
-    ip: String,
-    port: u16
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+struct LimitsSection {
+    max_connections: Option<u32>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+struct RetrySection {
+    token_key_file: Option<String>,
+    token_lifetime_s: Option<u64>,
+    rate_threshold: Option<u32>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+struct MetricsSection {
+    address: Option<String>,
+}
+
+/// A host served with its own certificate and files, selected by the SNI of the client.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct VirtualHost {
+    /// The host name, compared case-insensitively to the SNI and to the `:authority` of the
+    /// requests.
+    pub name: String,
+    /// The directory containing the priv.key and cert.pem of this host.
+    pub certs: String,
+    /// The directory the files of this host are served from.
+    pub www: String,
 }
 
-fn fetch_env() -> Result<EnvConfig, Box<dyn Error>> {
-    let config = envy::from_env()?;
-    Ok(config)
+/// The clients allowed to get the files under a path.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct AccessRule {
+    /// The path prefix the rule applies to, e.g. /private. It matches whole path segments.
+    pub path: String,
+    /// The common names of the client certificates allowed under the path.
+    pub clients: Vec<String>,
 }
 
 #[derive(Debug)]
@@ -34,6 +275,15 @@ pub struct Config {
     /// It contains the path to a directory the server can use for its general logs. These will
     /// be uploaded as part of the results artifact.
     pub logs: String,
+    /// The format of the access log written to access.log in the logs directory: common (the
+    /// Common Log Format), combined (with the referer and user agent), json or off. The common
+    /// and combined lines end with three more fields: the duration in milliseconds, the quinn
+    /// ID of the connection and 0-RTT for requests received in early data. It is combined if
+    /// not set.
+    pub access_log_format: String,
+    /// How often the access log is rotated: hourly, daily or never. The date is appended to the
+    /// name of the rotated files. It is daily if not set.
+    pub access_log_rotation: String,
     /// The name of the test case. You have to make sure a random string can be handled
     /// by your implementation.
     pub testcase: String,
@@ -41,35 +291,413 @@ pub struct Config {
     /// server implementation is expected to run on the given port 443 and serve files from
     /// this directory.
     pub www: String,
+    /// Whether the generated responses are served: /__gen/bytes/<n> (deterministic pseudo-random
+    /// bytes with their SHA-256), /__gen/delay/<ms> and /__gen/echo. They need no file in www
+    /// and no authentication, so they are meant for benchmarks. It is false if not set.
+    pub gen_routes: bool,
     /// The runner will create an X.509 certificate and chain to be used by the server during
     /// the handshake. The variable contains the path to a directory that contains a priv.key
-    /// and cert.pem file.
+    /// and cert.pem file. If it is empty, a self-signed certificate is generated.
     pub certs: String,
+    /// It contains the path to a file whose first line is the passphrase of the private keys
+    /// that are encrypted PKCS#8, the one in certs or the ones of the virtual hosts.
+    pub key_passphrase_file: String,
+    /// The host names of the generated self-signed certificate. It is localhost if not set.
+    pub self_signed_sans: Vec<String>,
+    /// It contains the path to the directory the generated certificate is kept in, so that it
+    /// survives restarts. If it is empty, a new certificate is generated on every start in a
+    /// temporary directory, removed when the server stops.
+    pub self_signed_dir: String,
     /// The IP the server has to listen on.
     pub ip: String,
-    /// The port the server has to listen on.
-    pub port: u16
+    /// The port the server has to listen on. It is 443 if not set.
+    pub port: u16,
+    /// A comma separated list of the QUIC versions the server accepts (e.g. 0x00000001,0xff00001d).
+    /// Clients offering any other version get a Version Negotiation packet. If it is empty,
+    /// the quinn defaults are used.
+    pub quic_versions: Vec<u32>,
+    /// The transport parameters: the [transport] section of the configuration file, then the
+    /// TOML file in TRANSPORT_CONFIG and the TP_* environment variables
+    /// (e.g. TP_MAX_CONCURRENT_BIDI_STREAMS).
+    pub transport: TransportParameters,
+    /// The maximum number of concurrent connections. If it is not set, the quinn default is used.
+    pub max_connections: Option<u32>,
+    /// It contains the path to the file holding the secret retry tokens are encrypted with, so
+    /// that they survive a restart. The file is created if it doesn't exist. If it is empty, a
+    /// new secret is used on every start.
+    pub retry_token_key_file: String,
+    /// The number of seconds a retry token is valid for. If it is not set, the quinn default
+    /// is used.
+    pub retry_token_lifetime_s: Option<u64>,
+    /// The number of connections per second above which clients have to go through a retry.
+    /// If it is not set, retry is only used for the retry testcase, and always then. Every client
+    /// that gets a retry pays the extra round trip, returning ones included.
+    pub retry_rate_threshold: Option<u32>,
+    /// The [[virtual_hosts]] of the configuration file. A client whose SNI names none of them
+    /// gets the certificate in `certs` and the files in `www`.
+    pub virtual_hosts: Vec<VirtualHost>,
+    /// Whether clients are asked for a certificate: none, request (clients without one are
+    /// accepted too) or require. It is none if not set.
+    pub client_auth: String,
+    /// It contains the path to a PEM bundle of the CAs the client certificates have to chain to.
+    /// It is required unless client_auth is none.
+    pub client_ca: String,
+    /// The [[access]] rules of the configuration file. A request under the path of a rule is
+    /// refused with 403 unless the client authenticated with one of its common names.
+    pub access: Vec<AccessRule>,
+    /// The TLS 1.3 cipher suites, in order of preference. If it is empty, the rustls defaults
+    /// are used, or only ChaCha20 for the chacha20 testcase.
+    pub cipher_suites: Vec<String>,
+    /// The key exchange groups, in order of preference. If it is empty, all the rustls groups
+    /// are used.
+    pub kx_groups: Vec<String>,
+    /// The number of seconds a session ticket can be used for. It is 12 hours if not set.
+    pub ticket_lifetime_s: u32,
+    /// The number of seconds after which the session ticket key is replaced. Tickets encrypted
+    /// with the previous key are still accepted, so it can't be shorter than the ticket lifetime,
+    /// which is its default.
+    pub ticket_rotation_s: u32,
+    /// Whether 0-RTT data is accepted. QUIC doesn't allow limiting its size in the ticket, so it
+    /// is all or nothing. If it is not set, it is true for the zerortt testcase and false
+    /// otherwise, as 0-RTT requests can be replayed. Requests received in 0-RTT are refused with
+    /// 425 Too Early unless their method is idempotent.
+    pub early_data: bool,
+    /// The number of used session tickets remembered so that they can't be replayed. When it is
+    /// full, tickets are refused until the oldest expire. It is 100000 if not set.
+    pub anti_replay_capacity: usize,
+    /// The address of the plain HTTP listener serving the Prometheus metrics on /metrics
+    /// (e.g. 127.0.0.1:9090). It has no authentication, so it should be a local address. If it
+    /// is not set, no metrics are served.
+    pub metrics_address: Option<SocketAddr>,
 }
 
 impl Config {
-    /// It fetches the envinromnent and returns a Config struct.
-    pub fn new() -> Config {
-        let config = fetch_env().expect("Error in parsing the environment");
-        let testcases = vec!["handshake", "transfer", "multihandshake", "chacha20", "retry", "resumption", "zerortt", "transportparameter", "goodput", "optimize"];
-        if !testcases.into_iter().any(|el| String::from(el) == config.testcase) {
-            println!("exited with code 127");
-            std::process::exit(127);
-        }
-        // TODO: add validation of the config
-        Config {
-            sslkeylogfile: config.sslkeylogfile,
-            qlogdir: config.qlogdir,
-            logs: config.logs,
-            testcase: config.testcase,
-            www: config.www,
-            certs: config.certs,
-            ip: config.ip,
-            port: config.port
+    /// It reads the configuration with this precedence: command line flags, environment
+    /// variables, configuration file, defaults. It returns a validated Config struct, or all
+    /// the problems found at once.
+    pub fn new() -> Result<Config, ConfigError> {
+        Config::from_cli(CliConfig::from_args(), true)
+    }
+
+    /// The configuration of a server started inside another program: it serves `www` with a
+    /// self-signed certificate kept in `self_signed_dir` on a free port of [::1], and serves the
+    /// generated routes. Neither the environment nor a configuration file is read, as they are
+    /// meant for the program.
+    pub fn local(
+        www: &Path,
+        self_signed_dir: &Path,
+        logs: &Path,
+        sslkeylogfile: &str,
+    ) -> Result<Config, ConfigError> {
+        let cli = CliConfig {
+            www: Some(www.to_string_lossy().into_owned()),
+            self_signed_dir: Some(self_signed_dir.to_string_lossy().into_owned()),
+            logs: Some(logs.to_string_lossy().into_owned()),
+            sslkeylogfile: Some(sslkeylogfile.to_string()),
+            certs: Some(String::new()),
+            self_signed_sans: Some(String::from("localhost")),
+            testcase: Some(String::from("transfer")),
+            ip: Some(String::from("::1")),
+            port: Some(0),
+            access_log_format: Some(String::from("off")),
+            gen_routes: Some(true),
+            ..CliConfig::default()
+        };
+        Config::from_cli(cli, false)
+    }
+
+    /// `from_env` tells whether the `TP_*` variables are read, and whether SSLKEYLOGFILE is
+    /// exported for rustls. Without it the server is a local one, that may listen on port 0 to
+    /// get a free port.
+    fn from_cli(cli: CliConfig, from_env: bool) -> Result<Config, ConfigError> {
+        let mut problems = Vec::new();
+        let file: FileConfig = match &cli.config_file {
+            Some(path) => commons::read_toml_file(path).unwrap_or_else(|e| {
+                problems.push(format!("`--config-file`: {}", e));
+                FileConfig::default()
+            }),
+            None => FileConfig::default(),
+        };
+
+        let sslkeylogfile = config::required(cli.sslkeylogfile.or(file.tls.sslkeylogfile), "tls.sslkeylogfile", "sslkeylogfile", &mut problems);
+        let logs = config::required(cli.logs.or(file.logging.logs), "logging.logs", "logs", &mut problems);
+        let testcase = config::required(cli.testcase.or(file.testcase), "testcase", "testcase", &mut problems);
+        let www = config::required(cli.www.or(file.www), "www", "www", &mut problems);
+        let ip = config::required(cli.ip.or(file.listen.ip), "listen.ip", "ip", &mut problems);
+        let port = cli.port.or(file.listen.port).unwrap_or(443);
+
+        let testcases = vec!["handshake", "transfer", "multihandshake", "chacha20", "retry", "resumption", "zerortt", "transportparameter", "goodput", "optimize", "rebind-port", "rebind-addr", "connectionmigration", "ecn", "versionnegotiation"];
+        if !testcase.is_empty() && !testcases.into_iter().any(|el| el == testcase) {
+            return Err(ConfigError::UnsupportedTestcase(testcase));
+        }
+        let metrics_address = match cli.metrics_address.or(file.metrics.address) {
+            Some(address) if !address.is_empty() => match address.parse() {
+                Ok(address) => Some(address),
+                Err(e) => {
+                    problems.push(format!("`metrics.address`: {} is not a socket address: {}", address, e));
+                    None
+                }
+            },
+            _ => None,
+        };
+        let quic_versions = commons::parse_versions(&cli.quic_versions.or(file.quic.versions).unwrap_or_default())
+            .unwrap_or_else(|e| {
+                problems.push(format!("`quic.versions`: {}", e));
+                Vec::new()
+            });
+        let mut transport_defaults = TransportParameters::default();
+        if testcase == "transportparameter" {
+            transport_defaults.max_concurrent_bidi_streams = Some(10);
+        }
+        if testcase == "goodput" || testcase == "optimize" {
+            // The server sends the files, BBR keeps the best goodput on the lossy links of the runner
+            transport_defaults.congestion_controller = Some(String::from("bbr"));
+        }
+        let transport_defaults = transport_defaults.merge(file.transport);
+        let transport = if from_env {
+            TransportParameters::load(&cli.transport_config.unwrap_or_default(), transport_defaults.clone())
+                .unwrap_or_else(|e| {
+                    problems.push(format!("`transport`: {}", e));
+                    transport_defaults
+                })
+        } else {
+            transport_defaults
+        };
+        let max_connections = cli.max_connections.or(file.limits.max_connections);
+        let self_signed_sans = match cli.self_signed_sans {
+            Some(sans) => sans
+                .split(',')
+                .map(|san| san.trim().to_string())
+                .filter(|san| !san.is_empty())
+                .collect(),
+            None => file
+                .tls
+                .self_signed_sans
+                .unwrap_or_else(|| vec![String::from("localhost")]),
+        };
+        let virtual_hosts = file
+            .virtual_hosts
+            .into_iter()
+            .map(|host| VirtualHost {
+                name: host.name.to_lowercase(),
+                ..host
+            })
+            .collect();
+
+        let early_data = cli.early_data.or(file.tls.early_data).unwrap_or(testcase == "zerortt");
+        let ticket_lifetime_s = cli.ticket_lifetime_s.or(file.tls.ticket_lifetime_s).unwrap_or(12 * 60 * 60);
+
+        let config = Config {
+            sslkeylogfile,
+            qlogdir: cli.qlogdir.or(file.logging.qlogdir).unwrap_or_default(),
+            logs,
+            access_log_format: cli.access_log_format.or(file.logging.access_log_format).unwrap_or_else(|| String::from("combined")),
+            access_log_rotation: cli.access_log_rotation.or(file.logging.access_log_rotation).unwrap_or_else(|| String::from("daily")),
+            testcase,
+            www,
+            gen_routes: cli.gen_routes.or(file.gen_routes).unwrap_or(false),
+            certs: cli.certs.or(file.tls.certs).unwrap_or_default(),
+            key_passphrase_file: cli.key_passphrase_file.or(file.tls.key_passphrase_file).unwrap_or_default(),
+            self_signed_sans,
+            self_signed_dir: cli.self_signed_dir.or(file.tls.self_signed_dir).unwrap_or_default(),
+            ip,
+            port,
+            quic_versions,
+            transport,
+            max_connections,
+            retry_token_key_file: cli.retry_token_key_file.or(file.retry.token_key_file).unwrap_or_default(),
+            retry_token_lifetime_s: cli.retry_token_lifetime_s.or(file.retry.token_lifetime_s),
+            retry_rate_threshold: cli.retry_rate_threshold.or(file.retry.rate_threshold),
+            virtual_hosts,
+            client_auth: cli.client_auth.or(file.tls.client_auth).unwrap_or_else(|| String::from("none")),
+            client_ca: cli.client_ca.or(file.tls.client_ca).unwrap_or_default(),
+            access: file.access,
+            cipher_suites: config::split_list(cli.cipher_suites, file.tls.cipher_suites),
+            kx_groups: config::split_list(cli.kx_groups, file.tls.kx_groups),
+            ticket_lifetime_s,
+            ticket_rotation_s: cli.ticket_rotation_s.or(file.tls.ticket_rotation_s).unwrap_or(ticket_lifetime_s),
+            early_data,
+            anti_replay_capacity: cli.anti_replay_capacity.or(file.tls.anti_replay_capacity).unwrap_or(100_000),
+            metrics_address,
+        };
+        if let Err(ConfigError::Invalid(found)) = config.validate(!from_env) {
+            problems.extend(found);
+        }
+        validation::into_result(problems)?;
+
+        // rustls::KeyLogFile reads the variable by itself, so a value given on the command line
+        // or in the configuration file has to be exported
+        if from_env {
+            std::env::set_var("SSLKEYLOGFILE", &config.sslkeylogfile);
+        }
+        Ok(config)
+    }
+
+    /// It checks that the directories exist with the right permissions, the certificates can
+    /// be used and the addresses parse. All the problems are reported together. `any_port`
+    /// accepts port 0, for which the system picks a free port.
+    pub fn validate(&self, any_port: bool) -> Result<(), ConfigError> {
+        let mut problems = Vec::new();
+        if !self.www.is_empty() {
+            validation::check_readable_dir("www", &self.www, &mut problems);
         }
+        if !self.certs.is_empty() {
+            if let Err(e) = certs_configuration::load_certified_key(Path::new(&self.certs), &self.key_passphrase_file) {
+                problems.push(format!("`tls.certs`: no usable key pair in {}: {}", self.certs, e));
+            }
+        } else {
+            if self.self_signed_sans.is_empty() {
+                problems.push(String::from("`tls.self_signed_sans`: at least one host name is needed"));
+            }
+            for san in &self.self_signed_sans {
+                if san.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>().is_ok() {
+                    problems.push(format!("`tls.self_signed_sans`: {} is an IP address, clients only verify host names", san));
+                }
+            }
+            if !self.self_signed_dir.is_empty() {
+                validation::check_writable_dir("tls.self_signed_dir", &self.self_signed_dir, &mut problems);
+            }
+        }
+        if !self.logs.is_empty() {
+            validation::check_writable_dir("logging.logs", &self.logs, &mut problems);
+        }
+        if !access_log::FORMATS.contains(&self.access_log_format.as_str()) {
+            problems.push(format!("`logging.access_log_format`: {} is not one of {}", self.access_log_format, access_log::FORMATS.join(", ")));
+        }
+        if !access_log::ROTATIONS.contains(&self.access_log_rotation.as_str()) {
+            problems.push(format!("`logging.access_log_rotation`: {} is not one of {}", self.access_log_rotation, access_log::ROTATIONS.join(", ")));
+        }
+        if !self.qlogdir.is_empty() {
+            validation::check_writable_dir("logging.qlogdir", &self.qlogdir, &mut problems);
+        }
+        if !self.sslkeylogfile.is_empty() {
+            validation::check_writable_file("tls.sslkeylogfile", &self.sslkeylogfile, &mut problems);
+        }
+        if !self.ip.is_empty() && self.ip.parse::<IpAddr>().is_err() {
+            problems.push(format!("`listen.ip`: {} is not an IP address", self.ip));
+        }
+        if self.port == 0 && !any_port {
+            problems.push(String::from("`listen.port`: must be between 1 and 65535"));
+        }
+        if self.max_connections == Some(0) {
+            problems.push(String::from("`limits.max_connections`: must be greater than 0"));
+        }
+        if !self.retry_token_key_file.is_empty() {
+            let path = Path::new(&self.retry_token_key_file);
+            if path.exists() {
+                if let Err(e) = fs::read(path) {
+                    problems.push(format!("`retry.token_key_file`: cannot read {}: {}", self.retry_token_key_file, e));
+                }
+            } else {
+                validation::check_writable_file("retry.token_key_file", &self.retry_token_key_file, &mut problems);
+            }
+        }
+        if self.retry_token_lifetime_s == Some(0) {
+            problems.push(String::from("`retry.token_lifetime_s`: must be greater than 0"));
+        }
+        if self.retry_rate_threshold == Some(0) {
+            problems.push(String::from("`retry.rate_threshold`: must be greater than 0"));
+        }
+        let mut names = HashSet::new();
+        for (i, host) in self.virtual_hosts.iter().enumerate() {
+            if host.name.is_empty() {
+                problems.push(format!("`virtual_hosts[{}].name`: must not be empty", i));
+            } else if !names.insert(&host.name) {
+                problems.push(format!("`virtual_hosts[{}].name`: {} is used twice", i, host.name));
+            }
+            validation::check_readable_dir(&format!("virtual_hosts[{}].www", i), &host.www, &mut problems);
+            if let Err(e) = certs_configuration::load_certified_key(Path::new(&host.certs), &self.key_passphrase_file) {
+                problems.push(format!("`virtual_hosts[{}].certs`: no usable key pair in {}: {}", i, host.certs, e));
+            }
+        }
+        match self.client_auth.as_str() {
+            "none" => {}
+            "request" | "require" => {
+                if self.client_ca.is_empty() {
+                    problems.push(format!("`tls.client_ca`: missing, required by client_auth {}", self.client_auth));
+                } else if let Err(e) = certificates::read_roots(&self.client_ca) {
+                    problems.push(format!("`tls.client_ca`: {}", e));
+                }
+            }
+            other => problems.push(format!("`tls.client_auth`: {} is not one of none, request, require", other)),
+        }
+        for (i, rule) in self.access.iter().enumerate() {
+            if !rule.path.starts_with('/') {
+                problems.push(format!("`access[{}].path`: {} does not start with /", i, rule.path));
+            }
+        }
+        if !self.access.is_empty() && self.client_auth == "none" {
+            problems.push(String::from("`access`: rules need client_auth request or require"));
+        }
+        match tls_config::cipher_suites(&self.cipher_suites, &self.testcase) {
+            Ok(suites) if suites.is_empty() => problems.push(String::from("`tls.cipher_suites`: no cipher suite left")),
+            Ok(_) => {}
+            Err(e) => problems.push(format!("`tls.cipher_suites`: {}", e)),
+        }
+        if let Err(e) = tls_config::kx_groups(&self.kx_groups) {
+            problems.push(format!("`tls.kx_groups`: {}", e));
+        }
+        if self.ticket_lifetime_s == 0 || self.ticket_lifetime_s > 7 * 24 * 60 * 60 {
+            problems.push(format!("`tls.ticket_lifetime_s`: {} is not between 1 and 604800 (7 days)", self.ticket_lifetime_s));
+        }
+        if self.ticket_rotation_s < self.ticket_lifetime_s {
+            problems.push(format!("`tls.ticket_rotation_s`: {} is shorter than ticket_lifetime_s {}", self.ticket_rotation_s, self.ticket_lifetime_s));
+        }
+        if self.anti_replay_capacity == 0 {
+            problems.push(String::from("`tls.anti_replay_capacity`: must be greater than 0"));
+        }
+        self.transport.check(&mut problems);
+        validation::into_result(problems)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_local_server_gets_a_free_port() {
+        let dir = std::env::temp_dir().join(format!("quic-implementation-local-{}", std::process::id()));
+        let (www, certs, logs) = (dir.join("www"), dir.join("certs"), dir.join("logs"));
+        for dir in [&www, &certs, &logs] {
+            fs::create_dir_all(dir).unwrap();
+        }
+        let config = Config::local(&www, &certs, &logs, &logs.join("keys.log").to_string_lossy());
+        fs::remove_dir_all(&dir).unwrap();
+        let config = config.unwrap();
+        assert_eq!((config.ip.as_str(), config.port), ("::1", 0));
+    }
+
+    /// The flags a runner passes for the testcase.
+    fn cli(testcase: &str) -> CliConfig {
+        CliConfig {
+            www: Some(String::from(".")),
+            logs: Some(String::from(".")),
+            sslkeylogfile: Some(String::from("keys.log")),
+            testcase: Some(String::from(testcase)),
+            ip: Some(String::from("::")),
+            ..CliConfig::default()
+        }
+    }
+
+    #[test]
+    fn a_configured_port_cant_be_0() {
+        let cli = CliConfig {
+            port: Some(0),
+            ..cli("transfer")
+        };
+        let err = Config::from_cli(cli, true).unwrap_err().to_string();
+        assert!(err.contains("`listen.port`"), "{}", err);
+    }
+
+    #[test]
+    fn early_data_is_only_accepted_by_default_for_zerortt() {
+        assert!(Config::from_cli(cli("zerortt"), false).unwrap().early_data);
+        assert!(!Config::from_cli(cli("transfer"), false).unwrap().early_data);
+        let cli = CliConfig {
+            early_data: Some(false),
+            ..cli("zerortt")
+        };
+        assert!(!Config::from_cli(cli, false).unwrap().early_data);
     }
 }
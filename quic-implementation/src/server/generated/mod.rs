@@ -0,0 +1,197 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use bytes::Bytes;
+use h3::{quic::BidiStream, server::RequestStream};
+
+use super::super::commons::payload::{self, Payload};
+use super::send_status;
+
+/// The path prefix of the generated responses.
+pub const PREFIX: &str = "/__gen/";
+/// The largest payload of /__gen/bytes.
+const MAX_BYTES: u64 = 1 << 30;
+/// The longest wait of /__gen/delay.
+const MAX_DELAY_MS: u64 = 60_000;
+/// The largest request body /__gen/echo sends back.
+const MAX_ECHO_LEN: usize = 16 << 20;
+/// The number of payload digests kept, so that repeated downloads aren't hashed again.
+const DIGEST_CACHE_LEN: usize = 1024;
+
+/// The routes answered without the files of www:
+///
+/// - /__gen/bytes/<n>?seed=<s>: n pseudo-random bytes, see `commons::payload`, with the seed, 0 by
+///   default, in x-gen-seed. Their SHA-256 is sent in a Repr-Digest header if an earlier
+///   response computed it, and otherwise in a Repr-Digest trailer, hashed while the payload is
+///   sent so that no response waits for it
+/// - /__gen/delay/<ms>: a short text once ms milliseconds have passed
+/// - /__gen/echo: the body of the request
+pub struct Generated {
+    digests: Mutex<HashMap<(u64, u64), String>>,
+}
+
+impl Generated {
+    pub fn new() -> Generated {
+        Generated {
+            digests: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// It answers a request whose path is `PREFIX` followed by `route`.
+    pub async fn respond<T>(
+        &self,
+        route: &str,
+        req: &http::Request<()>,
+        stream: RequestStream<T>,
+    ) -> Result<(http::StatusCode, usize), Box<dyn std::error::Error + Send>>
+    where
+        T: BidiStream<Bytes>,
+    {
+        let mut parts = route.splitn(2, '/');
+        match (parts.next(), parts.next()) {
+            (Some("bytes"), Some(len)) => match len.parse::<u64>() {
+                Ok(len) if len <= MAX_BYTES => {
+                    let seed = query_param(req, "seed")
+                        .and_then(|seed| seed.parse().ok())
+                        .unwrap_or(0);
+                    self.bytes(len, seed, stream).await
+                }
+                _ => send_status(stream, http::StatusCode::BAD_REQUEST).await,
+            },
+            (Some("delay"), Some(ms)) => match ms.parse::<u64>() {
+                Ok(ms) if ms <= MAX_DELAY_MS => delay(ms, stream).await,
+                _ => send_status(stream, http::StatusCode::BAD_REQUEST).await,
+            },
+            (Some("echo"), None) => echo(req, stream).await,
+            _ => send_status(stream, http::StatusCode::NOT_FOUND).await,
+        }
+    }
+
+    async fn bytes<T>(
+        &self,
+        len: u64,
+        seed: u64,
+        mut stream: RequestStream<T>,
+    ) -> Result<(http::StatusCode, usize), Box<dyn std::error::Error + Send>>
+    where
+        T: BidiStream<Bytes>,
+    {
+        let cached = self.digests.lock().unwrap().get(&(len, seed)).cloned();
+        let mut response = http::Response::builder()
+            .status(http::StatusCode::OK)
+            .header(http::header::CONTENT_TYPE, "application/octet-stream")
+            .header(http::header::CONTENT_LENGTH, len)
+            .header("x-gen-seed", seed);
+        response = match &cached {
+            Some(digest) => response.header("repr-digest", digest),
+            None => response.header(http::header::TRAILER, "repr-digest"),
+        };
+        let response = response.body(()).unwrap();
+        stream.send_response(response).await?;
+
+        let mut payload = Payload::new(seed);
+        let mut context = cached
+            .is_none()
+            .then(|| ring::digest::Context::new(&ring::digest::SHA256));
+        let mut left = len;
+        while left > 0 {
+            let mut chunk = vec![0u8; left.min(payload::CHUNK_LEN as u64) as usize];
+            payload.fill(&mut chunk);
+            if let Some(context) = &mut context {
+                context.update(&chunk);
+            }
+            left -= chunk.len() as u64;
+            stream.send_data(Bytes::from(chunk)).await?;
+        }
+        if let Some(context) = context {
+            let digest = repr_digest(context.finish());
+            let mut trailers = http::HeaderMap::new();
+            trailers.insert("repr-digest", digest.parse().unwrap());
+            stream.send_trailers(trailers).await?;
+            self.cache_digest(len, seed, digest);
+        }
+        stream.finish().await?;
+        Ok((http::StatusCode::OK, len as usize))
+    }
+
+    /// It keeps the digest of a payload for the next requests of the same size and seed.
+    fn cache_digest(&self, len: u64, seed: u64, digest: String) {
+        let mut digests = self.digests.lock().unwrap();
+        if digests.len() >= DIGEST_CACHE_LEN {
+            digests.clear();
+        }
+        digests.insert((len, seed), digest);
+    }
+}
+
+/// The Repr-Digest value (RFC 9530) of a SHA-256.
+fn repr_digest(digest: ring::digest::Digest) -> String {
+    format!("sha-256=:{}:", base64::encode(digest.as_ref()))
+}
+
+async fn delay<T>(
+    ms: u64,
+    mut stream: RequestStream<T>,
+) -> Result<(http::StatusCode, usize), Box<dyn std::error::Error + Send>>
+where
+    T: BidiStream<Bytes>,
+{
+    tokio::time::sleep(Duration::from_millis(ms)).await;
+    let body = format!("delayed {} ms\n", ms);
+    let response = http::Response::builder()
+        .status(http::StatusCode::OK)
+        .header(http::header::CONTENT_TYPE, "text/plain")
+        .header(http::header::CONTENT_LENGTH, body.len())
+        .body(())
+        .unwrap();
+    stream.send_response(response).await?;
+    let len = body.len();
+    stream.send_data(Bytes::from(body)).await?;
+    stream.finish().await?;
+    Ok((http::StatusCode::OK, len))
+}
+
+async fn echo<T>(
+    req: &http::Request<()>,
+    mut stream: RequestStream<T>,
+) -> Result<(http::StatusCode, usize), Box<dyn std::error::Error + Send>>
+where
+    T: BidiStream<Bytes>,
+{
+    let mut body = Vec::new();
+    while let Some(chunk) = stream.recv_data().await? {
+        if body.len() + chunk.len() > MAX_ECHO_LEN {
+            return send_status(stream, http::StatusCode::PAYLOAD_TOO_LARGE).await;
+        }
+        body.extend_from_slice(&chunk);
+    }
+    let digest = ring::digest::digest(&ring::digest::SHA256, &body);
+    let content_type = req
+        .headers()
+        .get(http::header::CONTENT_TYPE)
+        .cloned()
+        .unwrap_or_else(|| http::HeaderValue::from_static("application/octet-stream"));
+    let response = http::Response::builder()
+        .status(http::StatusCode::OK)
+        .header(http::header::CONTENT_TYPE, content_type)
+        .header(http::header::CONTENT_LENGTH, body.len())
+        .header("repr-digest", repr_digest(digest))
+        .body(())
+        .unwrap();
+    stream.send_response(response).await?;
+    let len = body.len();
+    if len > 0 {
+        stream.send_data(Bytes::from(body)).await?;
+    }
+    stream.finish().await?;
+    Ok((http::StatusCode::OK, len))
+}
+
+/// The value of a parameter of the query string of the request.
+fn query_param<'a>(req: &'a http::Request<()>, name: &str) -> Option<&'a str> {
+    req.uri().query()?.split('&').find_map(|pair| {
+        let (key, value) = pair.split_once('=')?;
+        (key == name).then_some(value)
+    })
+}
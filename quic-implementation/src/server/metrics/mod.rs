@@ -0,0 +1,312 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// The upper bounds, in seconds, of the handshake latency buckets.
+const HANDSHAKE_BUCKETS: &[f64] = &[0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0];
+/// The upper bounds, in seconds, of the request duration buckets.
+const REQUEST_BUCKETS: &[f64] = &[
+    0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0, 10.0, 30.0, 60.0,
+];
+/// The largest scrape request read, headers included.
+const MAX_REQUEST_LEN: usize = 8192;
+/// The time a scraper has to send its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The counters, gauges and histograms of the server, rendered in the Prometheus text format.
+#[derive(Debug)]
+pub struct Metrics {
+    connections_accepted: AtomicU64,
+    connections_refused: AtomicU64,
+    handshake_seconds: Histogram,
+    active_connections: AtomicI64,
+    active_streams: AtomicI64,
+    requests: Mutex<BTreeMap<u16, u64>>,
+    request_seconds: Histogram,
+    bytes_served: AtomicU64,
+    retried_connections: AtomicU64,
+    zero_rtt_accepted: AtomicU64,
+}
+
+#[derive(Debug)]
+struct Histogram {
+    bounds: &'static [f64],
+    buckets: Vec<AtomicU64>,
+    sum_micros: AtomicU64,
+    count: AtomicU64,
+}
+
+/// It counts a connection or stream as active until it is dropped.
+pub struct Active {
+    metrics: Arc<Metrics>,
+    gauge: fn(&Metrics) -> &AtomicI64,
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        Metrics {
+            connections_accepted: AtomicU64::new(0),
+            connections_refused: AtomicU64::new(0),
+            handshake_seconds: Histogram::new(HANDSHAKE_BUCKETS),
+            active_connections: AtomicI64::new(0),
+            active_streams: AtomicI64::new(0),
+            requests: Mutex::new(BTreeMap::new()),
+            request_seconds: Histogram::new(REQUEST_BUCKETS),
+            bytes_served: AtomicU64::new(0),
+            retried_connections: AtomicU64::new(0),
+            zero_rtt_accepted: AtomicU64::new(0),
+        }
+    }
+
+    /// A connection being served. With 0-RTT, that starts before the end of its handshake.
+    pub fn connection_opened(self: &Arc<Self>) -> Active {
+        Active::new(self, |metrics| &metrics.active_connections)
+    }
+
+    /// A connection whose handshake failed.
+    pub fn connection_refused(&self) {
+        self.connections_refused.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A connection whose handshake succeeded.
+    pub fn handshake_complete(&self, latency: Duration, zero_rtt_accepted: bool) {
+        self.connections_accepted.fetch_add(1, Ordering::Relaxed);
+        self.handshake_seconds.observe(latency);
+        if zero_rtt_accepted {
+            self.zero_rtt_accepted.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn stream_opened(self: &Arc<Self>) -> Active {
+        Active::new(self, |metrics| &metrics.active_streams)
+    }
+
+    pub fn request_served(&self, status: http::StatusCode, duration: Duration) {
+        *self
+            .requests
+            .lock()
+            .unwrap()
+            .entry(status.as_u16())
+            .or_insert(0) += 1;
+        self.request_seconds.observe(duration);
+    }
+
+    pub fn bytes_served(&self, bytes: usize) {
+        self.bytes_served.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// A connection that arrived while clients had to go through a retry, so it completed one.
+    /// quinn doesn't tell how many Retry packets it sent, the clients that never answered them
+    /// aren't counted.
+    pub fn connection_retried(&self) {
+        self.retried_connections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// It renders the metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        counter(
+            &mut out,
+            "quic_connections_accepted_total",
+            "Connections whose handshake succeeded.",
+            self.connections_accepted.load(Ordering::Relaxed),
+        );
+        counter(
+            &mut out,
+            "quic_connections_refused_total",
+            "Connections whose handshake failed.",
+            self.connections_refused.load(Ordering::Relaxed),
+        );
+        self.handshake_seconds.render(
+            &mut out,
+            "quic_handshake_duration_seconds",
+            "Time from the first packet of a connection to the end of its handshake.",
+        );
+        gauge(
+            &mut out,
+            "quic_active_connections",
+            "Connections being served.",
+            self.active_connections.load(Ordering::Relaxed),
+        );
+        gauge(
+            &mut out,
+            "http3_active_streams",
+            "Request streams being served.",
+            self.active_streams.load(Ordering::Relaxed),
+        );
+        let _ = writeln!(
+            out,
+            "# HELP http3_requests_total Requests answered, by status."
+        );
+        let _ = writeln!(out, "# TYPE http3_requests_total counter");
+        for (status, count) in self.requests.lock().unwrap().iter() {
+            let _ = writeln!(
+                out,
+                "http3_requests_total{{status=\"{}\"}} {}",
+                status, count
+            );
+        }
+        self.request_seconds.render(
+            &mut out,
+            "http3_request_duration_seconds",
+            "Time from receiving a request to finishing its response.",
+        );
+        counter(
+            &mut out,
+            "http3_response_bytes_total",
+            "Bytes of response bodies sent.",
+            self.bytes_served.load(Ordering::Relaxed),
+        );
+        counter(
+            &mut out,
+            "quic_retried_connections_total",
+            "Connections that answered a Retry packet.",
+            self.retried_connections.load(Ordering::Relaxed),
+        );
+        counter(
+            &mut out,
+            "quic_zero_rtt_accepted_total",
+            "Connections whose 0-RTT data was accepted.",
+            self.zero_rtt_accepted.load(Ordering::Relaxed),
+        );
+        out
+    }
+}
+
+impl Histogram {
+    fn new(bounds: &'static [f64]) -> Histogram {
+        Histogram {
+            bounds,
+            buckets: bounds.iter().map(|_| AtomicU64::new(0)).collect(),
+            sum_micros: AtomicU64::new(0),
+            count: AtomicU64::new(0),
+        }
+    }
+
+    fn observe(&self, value: Duration) {
+        let seconds = value.as_secs_f64();
+        if let Some(i) = self.bounds.iter().position(|&bound| seconds <= bound) {
+            self.buckets[i].fetch_add(1, Ordering::Relaxed);
+        }
+        self.sum_micros
+            .fetch_add(value.as_micros() as u64, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn render(&self, out: &mut String, name: &str, help: &str) {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} histogram", name);
+        // The buckets are stored apart and are cumulative in the output
+        let mut cumulative = 0;
+        for (bound, bucket) in self.bounds.iter().zip(&self.buckets) {
+            cumulative += bucket.load(Ordering::Relaxed);
+            let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bound, cumulative);
+        }
+        let count = self.count.load(Ordering::Relaxed);
+        let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, count);
+        let _ = writeln!(
+            out,
+            "{}_sum {}",
+            name,
+            self.sum_micros.load(Ordering::Relaxed) as f64 / 1e6
+        );
+        let _ = writeln!(out, "{}_count {}", name, count);
+    }
+}
+
+impl Active {
+    fn new(metrics: &Arc<Metrics>, gauge: fn(&Metrics) -> &AtomicI64) -> Active {
+        gauge(metrics).fetch_add(1, Ordering::Relaxed);
+        Active {
+            metrics: metrics.clone(),
+            gauge,
+        }
+    }
+}
+
+impl Drop for Active {
+    fn drop(&mut self) {
+        (self.gauge)(&self.metrics).fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+fn counter(out: &mut String, name: &str, help: &str, value: u64) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} counter", name);
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, value: i64) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} gauge", name);
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+/// It listens on `addr` for plain HTTP scrapes of /metrics. The listener has no authentication,
+/// so it should stay on a local address.
+pub async fn serve(metrics: Arc<Metrics>, addr: SocketAddr) -> Result<(), Box<dyn Error>> {
+    let listener = TcpListener::bind(addr).await?;
+    println!("Metrics on http://{}/metrics", listener.local_addr()?);
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((socket, _)) => {
+                    let metrics = metrics.clone();
+                    tokio::spawn(async move {
+                        if let Err(err) = scrape(socket, &metrics).await {
+                            println!("Metrics scrape failed: {:?}", err);
+                        }
+                    });
+                }
+                Err(err) => println!("Unable to accept a metrics connection: {:?}", err),
+            }
+        }
+    });
+    Ok(())
+}
+
+/// It answers one HTTP/1.x request and closes the connection.
+async fn scrape(mut socket: TcpStream, metrics: &Metrics) -> std::io::Result<()> {
+    let mut request = Vec::new();
+    let read_request = async {
+        let mut buf = [0u8; 1024];
+        while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+            let read = socket.read(&mut buf).await?;
+            if read == 0 || request.len() + read > MAX_REQUEST_LEN {
+                return Ok(false);
+            }
+            request.extend_from_slice(&buf[..read]);
+        }
+        Ok::<_, std::io::Error>(true)
+    };
+    match tokio::time::timeout(REQUEST_TIMEOUT, read_request).await {
+        Ok(Ok(true)) => {}
+        Ok(Ok(false)) | Err(_) => return Ok(()),
+        Ok(Err(err)) => return Err(err),
+    }
+    let request_line = String::from_utf8_lossy(&request);
+    let mut parts = request_line.split_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", metrics.render()),
+        (Some("GET"), _) => ("404 Not Found", String::from("Not Found\n")),
+        _ => (
+            "405 Method Not Allowed",
+            String::from("Method Not Allowed\n"),
+        ),
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    socket.write_all(response.as_bytes()).await?;
+    socket.shutdown().await
+}
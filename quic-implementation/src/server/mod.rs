@@ -1,63 +1,253 @@
 use std::error::Error;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
 use std::path::Path;
 use std::sync::Arc;
-use std::{
-    fs,
-    path::{self, PathBuf},
-    str,
-};
+use std::time::{Duration, Instant};
+use std::{fs, path, str};
 
 use bytes::Bytes;
+use futures::Future;
 use futures::StreamExt;
 use h3::{quic::BidiStream, server::RequestStream};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::oneshot;
 
+use super::commons;
+
+use access_log::AccessLog;
+use authorization::ClientIdentity;
+use early_data::EarlyData;
+use env_parser::AccessRule;
+use generated::Generated;
+use metrics::Metrics;
+
+mod access_log;
+mod authorization;
+mod cert_resolver;
 mod certs_configuration;
+mod early_data;
 mod env_parser;
+mod generated;
+mod metrics;
+mod retry;
+mod self_signed;
 mod setup_logs;
+mod tickets;
+mod virtual_hosts;
 
 pub async fn run_server() -> Result<(), Box<dyn std::error::Error>> {
-    let config = env_parser::Config::new();
+    let config = env_parser::Config::new()?;
     println!("{:#?}", config);
 
     setup_logs::setup_logs(&config);
 
+    // Stopping on SIGTERM and SIGINT drops the server, so that its temporary files are removed
+    let mut terminate = signal(SignalKind::terminate())?;
+    tokio::select! {
+        served = serve(config, None) => served,
+        _ = terminate.recv() => {
+            println!("SIGTERM received, stopping");
+            Ok(())
+        }
+        _ = tokio::signal::ctrl_c() => {
+            println!("SIGINT received, stopping");
+            Ok(())
+        }
+    }
+}
+
+/// It starts a server in this process on a free port of [::1], serving `www` with a
+/// self-signed certificate generated in `self_signed_dir`, and returns its address once it is
+/// listening. The benchmark of the client uses it.
+pub async fn spawn_local(
+    www: &Path,
+    self_signed_dir: &Path,
+    logs: &Path,
+    sslkeylogfile: &str,
+) -> Result<SocketAddr, Box<dyn std::error::Error>> {
+    let config = env_parser::Config::local(www, self_signed_dir, logs, sslkeylogfile)?;
+    let (ready, listening) = oneshot::channel();
+    tokio::spawn(async move {
+        if let Err(err) = serve(config, Some(ready)).await {
+            println!("Local server failed: {}", err);
+        }
+    });
+    Ok(listening.await?)
+}
+
+/// It serves the files of the configuration until the endpoint is closed. `ready` gets the
+/// address of the endpoint once it is listening.
+async fn serve(
+    mut config: env_parser::Config,
+    ready: Option<oneshot::Sender<SocketAddr>>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // Kept until the server stops, a temporary certificate is removed with it
+    let self_signed = if config.certs.is_empty() {
+        Some(self_signed::prepare(&config)?)
+    } else {
+        None
+    };
+    if let Some(self_signed) = &self_signed {
+        config.certs = self_signed.certs();
+    }
+
     let crypto = certs_configuration::get_server_crypto(&config)?;
-    let mut server_config = h3_quinn::quinn::ServerConfig::with_crypto(Arc::new(crypto));
-    if config.testcase == "transportparameter" {
-        Arc::get_mut(&mut server_config.transport)
-            .unwrap()
-            .max_concurrent_bidi_streams(10_u8.into());
+    let token_key = if config.retry_token_key_file.is_empty() {
+        retry::random_token_key()?
+    } else {
+        retry::load_token_key(&config.retry_token_key_file)?
+    };
+    let mut server_config = h3_quinn::quinn::ServerConfig::new(
+        Arc::new(commons::handshake::ServerCrypto(Arc::new(crypto))),
+        token_key,
+    );
+    config
+        .transport
+        .apply(Arc::get_mut(&mut server_config.transport).unwrap())?;
+    println!("{:#?}", server_config.transport);
+    if let Some(max_connections) = config.max_connections {
+        server_config.concurrent_connections(max_connections);
+    }
+    if let Some(lifetime) = config.retry_token_lifetime_s {
+        server_config.retry_token_lifetime(Duration::from_secs(lifetime));
     }
     if config.testcase == "retry" {
         server_config.use_retry(true);
     }
+    let mut retry_policy = match config.retry_rate_threshold {
+        Some(threshold) if config.testcase != "retry" => {
+            Some(retry::RetryPolicy::new(server_config.clone(), threshold))
+        }
+        _ => None,
+    };
 
-    let port = config.port;
-    let addr = format!("[::]:{:}", port).parse()?;
-    let (endpoint, mut incoming) = h3_quinn::quinn::Endpoint::server(server_config, addr)?;
+    let mut endpoint_config = h3_quinn::quinn::EndpointConfig::default();
+    if !config.quic_versions.is_empty() {
+        endpoint_config.supported_versions(config.quic_versions.clone());
+    }
+    config.transport.apply_endpoint(&mut endpoint_config)?;
+    println!("{:#?}", endpoint_config);
+
+    let ip = if config.ip.is_empty() {
+        IpAddr::V6(Ipv6Addr::UNSPECIFIED)
+    } else {
+        config.ip.parse()?
+    };
+    let socket = std::net::UdpSocket::bind(SocketAddr::new(ip, config.port))?;
+    let (endpoint, mut incoming) =
+        h3_quinn::quinn::Endpoint::new(endpoint_config, Some(server_config), socket)?;
 
     println!(
         "Listening on port {:?}",
         endpoint.local_addr().unwrap().port()
     );
+    if let Some(ready) = ready {
+        let _ = ready.send(endpoint.local_addr()?);
+    }
+
+    let virtual_hosts = Arc::new(virtual_hosts::VirtualHosts::new(&config));
+    let access = Arc::new(config.access.clone());
+    // Before the handshake completes, the client certificate isn't verified yet
+    let accept_early = config.early_data && config.client_auth == "none";
+    let congestion_controller = config.transport.congestion_controller_name().to_string();
+    let metrics = Arc::new(Metrics::new());
+    let access_log = AccessLog::new(&config).map(Arc::new);
+    let generated = config.gen_routes.then(|| Arc::new(Generated::new()));
+    if let Some(metrics_address) = config.metrics_address {
+        metrics::serve(metrics.clone(), metrics_address).await?;
+    }
 
     while let Some(new_conn) = incoming.next().await {
         println!("New connection being attempted");
-        let www = config.www.clone();
+        let started = Instant::now();
+        // The client had to answer a retry if it was required when its first packet arrived
+        let retried = match &retry_policy {
+            Some(retry_policy) => retry_policy.is_active(),
+            None => config.testcase == "retry",
+        };
+        if retried {
+            metrics.connection_retried();
+        }
+        if let Some(retry_policy) = &mut retry_policy {
+            retry_policy.record(&endpoint);
+        }
+        let virtual_hosts = virtual_hosts.clone();
+        let access = access.clone();
+        let congestion_controller = congestion_controller.clone();
+        let metrics = metrics.clone();
+        let access_log = access_log.clone();
+        let generated = generated.clone();
 
         tokio::spawn(async move {
-            match new_conn.await {
+            let handshake_metrics = metrics.clone();
+            let on_handshake = move |zero_rtt_accepted: Option<bool>| match zero_rtt_accepted {
+                Some(zero_rtt_accepted) => {
+                    handshake_metrics.handshake_complete(started.elapsed(), zero_rtt_accepted)
+                }
+                None => handshake_metrics.connection_refused(),
+            };
+            match early_data::establish(new_conn, accept_early, on_handshake).await {
                 Ok(conn) => {
-                    println!("New connection now established");
+                    let _active = metrics.connection_opened();
+                    println!(
+                        "New connection now established, congestion controller: {}",
+                        congestion_controller
+                    );
+                    let connection = conn.connection.clone();
+                    let remote_addr = connection.remote_address();
+                    let server_name = connection
+                        .handshake_data()
+                        .and_then(|data| data.downcast::<commons::handshake::HandshakeData>().ok())
+                        .and_then(|data| data.server_name);
+                    let host = virtual_hosts.select(server_name.as_deref());
+                    let www = virtual_hosts.www(host.as_deref());
+                    println!("Serving {:?} from {}", server_name, www);
+                    let identity = ClientIdentity::of(&connection);
+                    println!("Client identity: {:?}", identity);
 
                     let mut h3_conn = h3::server::Connection::new(h3_quinn::Connection::new(conn))
                         .await
                         .unwrap();
 
-                    while let Some((req, stream)) = h3_conn.accept().await.unwrap() {
-                        println!("connection requested: {:#?}", req);
+                    while let Some((mut req, stream)) = h3_conn.accept().await.unwrap() {
+                        let active = metrics.stream_opened();
+                        if !early_data::is_handshake_complete(&connection) {
+                            req.extensions_mut().insert(EarlyData);
+                        }
+                        let entry =
+                            access_log::Entry::new(&req, remote_addr, connection.stable_id());
 
-                        tokio::spawn(handle_request(www.clone(), req, stream));
+                        // The :authority must name the host the certificate was chosen for
+                        match req.uri().host().map(String::from) {
+                            Some(authority) if virtual_hosts.select(Some(&authority)) != host => {
+                                println!("Misdirected request for {}", authority);
+                                tokio::spawn(track(
+                                    metrics.clone(),
+                                    access_log.clone(),
+                                    active,
+                                    entry,
+                                    send_status(stream, http::StatusCode::MISDIRECTED_REQUEST),
+                                ));
+                            }
+                            _ => {
+                                if let Some(identity) = &identity {
+                                    req.extensions_mut().insert(identity.clone());
+                                }
+                                tokio::spawn(track(
+                                    metrics.clone(),
+                                    access_log.clone(),
+                                    active,
+                                    entry,
+                                    handle_request(
+                                        www.clone(),
+                                        access.clone(),
+                                        generated.clone(),
+                                        req,
+                                        stream,
+                                    ),
+                                ));
+                            }
+                        }
                     }
                 }
                 Err(err) => {
@@ -70,23 +260,68 @@ pub async fn run_server() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+/// It records the status, body size and duration of a response in the metrics and the access
+/// log, its stream counting as active until then.
+async fn track(
+    metrics: Arc<Metrics>,
+    access_log: Option<Arc<AccessLog>>,
+    active: metrics::Active,
+    mut entry: access_log::Entry,
+    response: impl Future<Output = Result<(http::StatusCode, usize), Box<dyn std::error::Error + Send>>>,
+) -> Result<(), Box<dyn std::error::Error + Send>> {
+    let started = Instant::now();
+    let (status, bytes) = response.await?;
+    let duration = started.elapsed();
+    metrics.request_served(status, duration);
+    metrics.bytes_served(bytes);
+    if let Some(access_log) = access_log {
+        entry.complete(status, bytes, duration);
+        access_log.write(&entry);
+    }
+    drop(active);
+    Ok(())
+}
+
+/// It answers a request with a file of its host, or a generated response under /__gen/, and
+/// returns the status and the size of the body sent.
 async fn handle_request<T>(
     www: String,
+    access: Arc<Vec<AccessRule>>,
+    generated: Option<Arc<Generated>>,
     req: http::Request<()>,
     mut stream: RequestStream<T>,
-) -> Result<(), Box<dyn std::error::Error + Send>>
+) -> Result<(http::StatusCode, usize), Box<dyn std::error::Error + Send>>
 where
     T: BidiStream<Bytes>,
 {
+    let path = match normalize_path(req.uri().path()) {
+        Some(path) => path,
+        None => {
+            println!("illegal path {}", req.uri().path());
+            return send_status(stream, http::StatusCode::BAD_REQUEST).await;
+        }
+    };
+    let identity = req.extensions().get::<ClientIdentity>();
+    if !authorization::is_authorized(&access, &path, identity) {
+        println!("{:?} is not allowed to get {}", identity, path);
+        return send_status(stream, http::StatusCode::FORBIDDEN).await;
+    }
+    if req.extensions().get::<EarlyData>().is_some() {
+        println!("{} {} received in 0-RTT", req.method(), req.uri().path());
+        if !early_data::is_idempotent(req.method()) {
+            return send_status(stream, early_data::too_early()).await;
+        }
+    }
+    if let Some(generated) = &generated {
+        if let Some(route) = path.strip_prefix(generated::PREFIX) {
+            return generated.respond(route, &req, stream).await;
+        }
+    }
+
     let www_path = Path::new(&www);
-    let uri = http::Uri::try_from(req.uri()).unwrap();
-    let requested_file = uri.path();
-    let (_, requested_file) = requested_file.split_at(1);
-    let file_path = www_path.join(requested_file);
-    let path_total = req.uri().clone().into_parts().path_and_query.unwrap();
-    let path_total = path_total.path();
-
-    if !file_path.exists() {
+    let file_path = www_path.join(path.trim_start_matches('/'));
+
+    let answered = if !file_path.is_file() {
         println!("File not found: {:?}", file_path);
 
         let response = http::Response::builder()
@@ -102,13 +337,15 @@ where
                 println!("Unable to send response to connection peer: {:?}", err);
             }
         }
+        (http::StatusCode::NOT_FOUND, 0)
     } else {
         let response = http::Response::builder()
             .status(http::StatusCode::OK)
             .body(())
             .unwrap();
 
-        let file = process_get(www_path, path_total).unwrap();
+        let file = process_get(&file_path).unwrap();
+        let mut len = file.len();
 
         match stream.send_response(response).await {
             Ok(_) => {
@@ -125,33 +362,99 @@ where
             }
             Err(err) => {
                 println!("Unable to send response to connection peer: {:?}", err);
+                len = 0;
             }
         }
+        (http::StatusCode::OK, len)
     };
 
-    Ok(stream.finish().await?)
+    stream.finish().await?;
+    Ok(answered)
 }
 
-fn process_get(root: &Path, path: &str) -> Result<Vec<u8>, Box<dyn Error>> {
-    let path = Path::new(&path);
-    let mut real_path = PathBuf::from(root);
-    let mut components = path.components();
-    match components.next() {
-        Some(path::Component::RootDir) => {}
-        _ => {
-            println!("path must be absolute");
+async fn send_status<T>(
+    mut stream: RequestStream<T>,
+    status: http::StatusCode,
+) -> Result<(http::StatusCode, usize), Box<dyn std::error::Error + Send>>
+where
+    T: BidiStream<Bytes>,
+{
+    let response = http::Response::builder().status(status).body(()).unwrap();
+
+    match stream.send_response(response).await {
+        Ok(_) => {
+            println!("Response to connection successful");
+        }
+        Err(err) => {
+            println!("Unable to send response to connection peer: {:?}", err);
         }
     }
-    for c in components {
-        match c {
-            path::Component::Normal(x) => {
-                real_path.push(x);
-            }
-            x => {
-                println!("illegal component in path: {:?}", x);
+
+    stream.finish().await?;
+    Ok((status, 0))
+}
+
+fn process_get(file_path: &Path) -> Result<Vec<u8>, Box<dyn Error>> {
+    let data = fs::read(file_path)?;
+    Ok(data)
+}
+
+/// The path of a request made of its normal components only, so that the access rules and the
+/// file lookup see the same path. It is `None` for a relative path or one with `..`.
+fn normalize_path(path: &str) -> Option<String> {
+    let mut components = Path::new(path).components();
+    if components.next() != Some(path::Component::RootDir) {
+        return None;
+    }
+    let mut normalized = String::new();
+    for component in components {
+        match component {
+            path::Component::Normal(name) => {
+                normalized.push('/');
+                normalized.push_str(name.to_str()?);
             }
+            path::Component::CurDir => {}
+            _ => return None,
         }
     }
-    let data = fs::read(&real_path)?;
-    Ok(data)
+    if normalized.is_empty() {
+        normalized.push('/');
+    }
+    Some(normalized)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paths_keep_their_normal_components() {
+        assert_eq!(normalize_path("/").as_deref(), Some("/"));
+        assert_eq!(normalize_path("/a/b.txt").as_deref(), Some("/a/b.txt"));
+        assert_eq!(normalize_path("//a//b.txt/").as_deref(), Some("/a/b.txt"));
+    }
+
+    #[test]
+    fn current_directories_are_dropped() {
+        assert_eq!(
+            normalize_path("/./private/./a.txt").as_deref(),
+            Some("/private/a.txt")
+        );
+    }
+
+    #[test]
+    fn parent_directories_and_relative_paths_are_refused() {
+        assert_eq!(normalize_path("/public/../private/a.txt"), None);
+        assert_eq!(normalize_path("private/a.txt"), None);
+    }
+
+    #[test]
+    fn a_current_directory_doesnt_escape_the_access_rules() {
+        let rules = [AccessRule {
+            path: String::from("/private"),
+            clients: vec![String::from("alice")],
+        }];
+        let path = normalize_path("/./private/a.txt").unwrap();
+        assert!(!authorization::is_authorized(&rules, &path, None));
+    }
 }
@@ -0,0 +1,118 @@
+use std::error::Error;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use h3_quinn::quinn::{Endpoint, ServerConfig};
+use quinn_proto::crypto::HandshakeTokenKey;
+use ring::rand::{SecureRandom, SystemRandom};
+
+/// The size of the secret the retry token key is derived from, as quinn uses.
+const MASTER_KEY_LEN: usize = 64;
+
+/// The period the connection rate is measured over.
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+
+/// It returns the key retry tokens are encrypted with, derived from the secret in the file. If
+/// the file doesn't exist, a random secret is written to it, so that tokens stay valid across
+/// restarts.
+pub fn load_token_key(path: &str) -> Result<Arc<dyn HandshakeTokenKey>, Box<dyn Error>> {
+    let master_key = if Path::new(path).exists() {
+        let master_key = fs::read(path)?;
+        if master_key.len() < 32 {
+            Err(format!("{} holds less than 32 bytes", path))?;
+        }
+        master_key
+    } else {
+        let master_key = random_master_key()?;
+        fs::write(path, &master_key)?;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+        println!("Generated a retry token key in {}", path);
+        master_key
+    };
+    Ok(token_key(&master_key))
+}
+
+/// A retry token key derived from a random secret, as quinn makes by default. The tokens it
+/// encrypts don't survive a restart.
+pub fn random_token_key() -> Result<Arc<dyn HandshakeTokenKey>, Box<dyn Error>> {
+    Ok(token_key(&random_master_key()?))
+}
+
+fn random_master_key() -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut master_key = vec![0u8; MASTER_KEY_LEN];
+    SystemRandom::new()
+        .fill(&mut master_key)
+        .map_err(|_| "cannot generate a retry token key")?;
+    Ok(master_key)
+}
+
+fn token_key(master_key: &[u8]) -> Arc<dyn HandshakeTokenKey> {
+    let salt = ring::hkdf::Salt::new(ring::hkdf::HKDF_SHA256, &[]);
+    Arc::new(salt.extract(master_key))
+}
+
+/// It requires clients to go through a retry only while more than `threshold` connections per
+/// second are being accepted, by swapping the server config of the endpoint.
+///
+/// Returning clients aren't exempt. quinn 0.8 never sends NEW_TOKEN frames and its client can't
+/// put a stored token in its Initial, so issuing address validation tokens and keeping them on
+/// the client wait for a quinn that supports them.
+pub struct RetryPolicy {
+    server_config: ServerConfig,
+    threshold: u32,
+    window_start: Instant,
+    count: u32,
+    active: bool,
+}
+
+impl RetryPolicy {
+    pub fn new(server_config: ServerConfig, threshold: u32) -> RetryPolicy {
+        RetryPolicy {
+            server_config,
+            threshold,
+            window_start: Instant::now(),
+            count: 0,
+            active: false,
+        }
+    }
+
+    /// It counts a new connection, turning retry on as soon as the threshold is crossed and off
+    /// once a whole window stayed below it.
+    pub fn record(&mut self, endpoint: &Endpoint) {
+        if self.window_start.elapsed() >= RATE_WINDOW {
+            if self.active && self.count <= self.threshold {
+                self.set(endpoint, false);
+            }
+            self.window_start = Instant::now();
+            self.count = 0;
+        }
+        self.count += 1;
+        if !self.active && self.count > self.threshold {
+            self.set(endpoint, true);
+        }
+    }
+
+    /// Whether clients currently have to go through a retry.
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    fn set(&mut self, endpoint: &Endpoint, active: bool) {
+        let mut server_config = self.server_config.clone();
+        server_config.use_retry(active);
+        endpoint.set_server_config(Some(server_config));
+        self.active = active;
+        println!(
+            "Retry {} at {} connections in the last second",
+            if active {
+                "required"
+            } else {
+                "no longer required"
+            },
+            self.count
+        );
+    }
+}
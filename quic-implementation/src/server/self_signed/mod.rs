@@ -0,0 +1,121 @@
+use std::error::Error;
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+use rcgen::{BasicConstraints, Certificate, CertificateParams, DnType, IsCa};
+use ring::rand::{SecureRandom, SystemRandom};
+
+use super::env_parser::Config;
+
+/// The file the generated CA is written to, for clients to pass to --ca-file.
+const CA_FILE: &str = "ca.pem";
+
+/// The certs directory of a generated certificate. A temporary one is removed with its content
+/// when this is dropped.
+pub struct SelfSigned {
+    dir: PathBuf,
+    temporary: bool,
+}
+
+/// It returns a certs directory holding a certificate for the configured host names, signed by
+/// a CA generated along with it. A certificate kept in self_signed_dir is reused, delete the
+/// directory to generate a new one. Without self_signed_dir, the certificate is generated in a
+/// new temporary directory only the server can read. The CA certificate is printed and written
+/// to ca.pem so that clients can trust it.
+pub fn prepare(config: &Config) -> Result<SelfSigned, Box<dyn Error>> {
+    let self_signed = if config.self_signed_dir.is_empty() {
+        SelfSigned {
+            dir: create_temp_dir()?,
+            temporary: true,
+        }
+    } else {
+        SelfSigned {
+            dir: PathBuf::from(&config.self_signed_dir),
+            temporary: false,
+        }
+    };
+    let dir = &self_signed.dir;
+    let files = ["cert.pem", "priv.key", CA_FILE];
+    if !self_signed.temporary && files.iter().all(|file| dir.join(file).exists()) {
+        println!("Reusing the self-signed certificate in {:?}", dir);
+    } else {
+        generate(dir, &config.self_signed_sans)?;
+        println!(
+            "Generated a self-signed certificate for {:?} in {:?}",
+            config.self_signed_sans, dir
+        );
+    }
+    let ca_path = dir.join(CA_FILE);
+    println!(
+        "Clients can trust it with --ca-file {:?}:\n{}",
+        ca_path,
+        fs::read_to_string(&ca_path)?
+    );
+    Ok(self_signed)
+}
+
+impl SelfSigned {
+    /// The directory to use as certs.
+    pub fn certs(&self) -> String {
+        self.dir.to_string_lossy().into_owned()
+    }
+}
+
+impl Drop for SelfSigned {
+    fn drop(&mut self) {
+        if self.temporary {
+            if let Err(err) = fs::remove_dir_all(&self.dir) {
+                println!("Unable to remove {:?}: {}", self.dir, err);
+            }
+        }
+    }
+}
+
+/// It creates a directory with a random name in the temporary directory, that only the server
+/// can read. It fails rather than reuse a directory that already exists.
+fn create_temp_dir() -> Result<PathBuf, Box<dyn Error>> {
+    let mut suffix = [0u8; 8];
+    SystemRandom::new()
+        .fill(&mut suffix)
+        .map_err(|_| "unable to generate a temporary directory name")?;
+    let name: String = suffix.iter().map(|byte| format!("{:02x}", byte)).collect();
+    let dir = std::env::temp_dir().join(format!("quic-implementation-{}", name));
+    fs::DirBuilder::new().mode(0o700).create(&dir)?;
+    Ok(dir)
+}
+
+/// It writes a new CA (ca.pem) and a leaf signed by it (cert.pem, priv.key) into the directory.
+/// The key of the CA is dropped once the leaf is signed.
+fn generate(dir: &Path, sans: &[String]) -> Result<(), Box<dyn Error>> {
+    let mut ca_params = CertificateParams::new(Vec::new());
+    ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    ca_params
+        .distinguished_name
+        .push(DnType::CommonName, "quic-implementation development CA");
+    let ca = Certificate::from_params(ca_params)?;
+
+    let mut leaf_params = CertificateParams::new(sans.to_vec());
+    leaf_params
+        .distinguished_name
+        .push(DnType::CommonName, sans[0].as_str());
+    let leaf = Certificate::from_params(leaf_params)?;
+
+    fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(dir)?;
+    fs::write(dir.join(CA_FILE), ca.serialize_pem()?)?;
+    fs::write(dir.join("cert.pem"), leaf.serialize_pem_with_signer(&ca)?)?;
+    // The key is never readable by others, even while it is written
+    let mut key_file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(dir.join("priv.key"))?;
+    key_file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    key_file.write_all(leaf.serialize_private_key_pem().as_bytes())?;
+    Ok(())
+}
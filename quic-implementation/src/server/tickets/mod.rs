@@ -0,0 +1,169 @@
+use std::collections::{HashSet, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use ring::aead::{self, Aad, LessSafeKey, Nonce, UnboundKey, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+use rustls::server::ProducesTickets;
+
+/// A session ticket producer with a configurable lifetime and key rotation, whose tickets can
+/// only be used once. A replayed ClientHello therefore falls back to a full handshake and its
+/// 0-RTT data is rejected.
+pub struct Ticketer {
+    lifetime: Duration,
+    rotation: Duration,
+    random: SystemRandom,
+    keys: Mutex<Keys>,
+    strikes: Mutex<StrikeRegister>,
+}
+
+/// The key tickets are encrypted with and the previous one, still accepted for decryption.
+struct Keys {
+    current: LessSafeKey,
+    previous: Option<LessSafeKey>,
+    rotated_at: Instant,
+}
+
+/// The hashes of the tickets already used, until they expire. It holds at most `capacity`
+/// tickets: when it is full, tickets are refused rather than forgotten early.
+struct StrikeRegister {
+    capacity: usize,
+    expiries: VecDeque<([u8; 32], Instant)>,
+    used: HashSet<[u8; 32]>,
+}
+
+impl Ticketer {
+    /// It creates a ticketer whose tickets are valid for `lifetime`, encrypted with a key
+    /// replaced every `rotation`. The rotation must not be shorter than the lifetime, as only the
+    /// previous key is kept.
+    pub fn new(
+        lifetime: Duration,
+        rotation: Duration,
+        anti_replay_capacity: usize,
+    ) -> Result<Ticketer, ring::error::Unspecified> {
+        let random = SystemRandom::new();
+        let current = new_key(&random)?;
+        Ok(Ticketer {
+            lifetime,
+            rotation,
+            random,
+            keys: Mutex::new(Keys {
+                current,
+                previous: None,
+                rotated_at: Instant::now(),
+            }),
+            strikes: Mutex::new(StrikeRegister {
+                capacity: anti_replay_capacity,
+                expiries: VecDeque::new(),
+                used: HashSet::new(),
+            }),
+        })
+    }
+
+    /// It replaces the current key if it is older than the rotation period.
+    fn rotate(&self, keys: &mut Keys) {
+        if keys.rotated_at.elapsed() < self.rotation {
+            return;
+        }
+        match new_key(&self.random) {
+            Ok(key) => {
+                keys.previous = Some(std::mem::replace(&mut keys.current, key));
+                keys.rotated_at = Instant::now();
+                println!("Session ticket key rotated");
+            }
+            Err(_) => println!("Unable to generate a new session ticket key"),
+        }
+    }
+}
+
+impl ProducesTickets for Ticketer {
+    fn enabled(&self) -> bool {
+        true
+    }
+
+    fn lifetime(&self) -> u32 {
+        self.lifetime.as_secs() as u32
+    }
+
+    fn encrypt(&self, plain: &[u8]) -> Option<Vec<u8>> {
+        let mut keys = self.keys.lock().unwrap();
+        self.rotate(&mut keys);
+        let mut nonce = [0u8; NONCE_LEN];
+        self.random.fill(&mut nonce).ok()?;
+        let mut sealed = plain.to_vec();
+        keys.current
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::empty(),
+                &mut sealed,
+            )
+            .ok()?;
+        let mut ticket = nonce.to_vec();
+        ticket.extend(sealed);
+        Some(ticket)
+    }
+
+    fn decrypt(&self, cipher: &[u8]) -> Option<Vec<u8>> {
+        if cipher.len() < NONCE_LEN {
+            return None;
+        }
+        let plain = {
+            let mut keys = self.keys.lock().unwrap();
+            self.rotate(&mut keys);
+            let keys = &*keys;
+            std::iter::once(&keys.current)
+                .chain(keys.previous.as_ref())
+                .find_map(|key| open(key, cipher))?
+        };
+        let hash = ring::digest::digest(&ring::digest::SHA256, cipher);
+        let mut id = [0u8; 32];
+        id.copy_from_slice(hash.as_ref());
+        if !self
+            .strikes
+            .lock()
+            .unwrap()
+            .insert(id, Instant::now() + self.lifetime)
+        {
+            println!("Session ticket refused: already used or anti-replay cache full");
+            return None;
+        }
+        Some(plain)
+    }
+}
+
+impl StrikeRegister {
+    /// It records a ticket, returning false if it was already used or there is no room left.
+    fn insert(&mut self, id: [u8; 32], expiry: Instant) -> bool {
+        let now = Instant::now();
+        while let Some((old, old_expiry)) = self.expiries.front() {
+            if *old_expiry > now {
+                break;
+            }
+            self.used.remove(old);
+            self.expiries.pop_front();
+        }
+        if self.used.contains(&id) || self.used.len() >= self.capacity {
+            return false;
+        }
+        self.used.insert(id);
+        self.expiries.push_back((id, expiry));
+        true
+    }
+}
+
+fn new_key(random: &SystemRandom) -> Result<LessSafeKey, ring::error::Unspecified> {
+    let mut key = [0u8; 32];
+    random.fill(&mut key)?;
+    Ok(LessSafeKey::new(UnboundKey::new(
+        &aead::CHACHA20_POLY1305,
+        &key,
+    )?))
+}
+
+fn open(key: &LessSafeKey, ticket: &[u8]) -> Option<Vec<u8>> {
+    let (nonce, sealed) = ticket.split_at(NONCE_LEN);
+    let nonce = Nonce::try_assume_unique_for_key(nonce).ok()?;
+    let mut sealed = sealed.to_vec();
+    let plain = key.open_in_place(nonce, Aad::empty(), &mut sealed).ok()?;
+    Some(plain.to_vec())
+}
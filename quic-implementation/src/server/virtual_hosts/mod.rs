@@ -0,0 +1,37 @@
+use std::collections::HashMap;
+
+use super::env_parser::Config;
+
+/// It maps the virtual host names to the directory their files are served from. The names are
+/// stored in lowercase, as the configuration normalizes them.
+#[derive(Debug)]
+pub struct VirtualHosts {
+    default_www: String,
+    hosts: HashMap<String, String>,
+}
+
+impl VirtualHosts {
+    pub fn new(config: &Config) -> VirtualHosts {
+        VirtualHosts {
+            default_www: config.www.clone(),
+            hosts: config
+                .virtual_hosts
+                .iter()
+                .map(|host| (host.name.clone(), host.www.clone()))
+                .collect(),
+        }
+    }
+
+    /// The configured host with the given name, `None` standing for the default host.
+    pub fn select(&self, name: Option<&str>) -> Option<String> {
+        name.map(|name| name.to_lowercase())
+            .filter(|name| self.hosts.contains_key(name))
+    }
+
+    /// The directory the files of the host are served from.
+    pub fn www(&self, host: Option<&str>) -> String {
+        host.and_then(|host| self.hosts.get(host))
+            .unwrap_or(&self.default_www)
+            .clone()
+    }
+}
@@ -2,7 +2,7 @@
 
 SCRIPTDIR=`dirname "$(readlink -f "$0")"`
 
-${SCRIPTDIR}/quic-implementation/target/release/client > ${LOGS}/log.txt 2>&1
+${SCRIPTDIR}/quic-implementation/target/release/client --insecure > ${LOGS}/log.txt 2>&1
 
 retVal=$?
 if [ $retVal -eq 127 ]; then